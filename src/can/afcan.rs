@@ -0,0 +1,44 @@
+use libc::{c_int, sa_family_t};
+
+
+/* types defined in linux/can.h, which we use on Linux */
+
+#[repr(C)]
+pub struct sockaddr_can {
+    pub can_family: sa_family_t,
+    pub can_ifindex: c_int,
+    pub rx_id: u32,
+    pub tx_id: u32,
+}
+
+// struct can_frame for classic frames has the same layout, only with 8 data bytes
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct canfd_frame {
+    pub can_id: u32,
+    pub len: u8,
+    pub flags: u8,
+    pub res0: u8,
+    pub res1: u8,
+    pub data: [u8; 64],
+}
+
+
+/* constants used as arguments to functions */
+
+pub const CAN_RAW: c_int = 1;
+pub const SOL_CAN_RAW: c_int = 101; // SOL_CAN_BASE + CAN_RAW
+pub const CAN_RAW_FD_FRAMES: c_int = 5;
+
+pub const CAN_EFF_FLAG: u32 = 0x80000000; // extended frame format (29 bit identifier)
+pub const CAN_RTR_FLAG: u32 = 0x40000000; // remote transmission request
+pub const CAN_ERR_FLAG: u32 = 0x20000000; // error message frame
+
+pub const CAN_SFF_MASK: u32 = 0x000007FF; // standard frame format (11 bit identifier)
+pub const CAN_EFF_MASK: u32 = 0x1FFFFFFF; // extended frame format (29 bit identifier)
+
+pub const CANFD_BRS: u8 = 0x01; // bit rate switch (second bitrate for payload data)
+pub const CANFD_ESI: u8 = 0x02; // error state indicator of the transmitting node
+
+pub const CAN_MTU: usize = 16; // size of struct can_frame
+pub const CANFD_MTU: usize = 72; // size of struct canfd_frame
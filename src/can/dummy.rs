@@ -1,31 +1,37 @@
 use std::thread;
-use core::time;
-use crate::can::{CANMessage, CANAdaptor};
+use std::time::{Duration, Instant};
+use crate::can::{CANMessage, CANAdaptor, CANError, AdaptorConfig};
 
 
-pub struct DummyAdaptor {}
+pub struct DummyAdaptor {
+    next_message_at: Instant,
+}
 
 
 impl DummyAdaptor {
-    pub fn new() -> Result<Box<CANAdaptor>, &'static str> {
-        Ok(Box::new(DummyAdaptor {}))
+    pub fn create(_config: &AdaptorConfig) -> Result<Box<dyn CANAdaptor>, CANError> {
+        println!("DummyAdaptor: Waiting");
+        Ok(Box::new(DummyAdaptor { next_message_at: Instant::now() + Duration::from_secs(5) }))
     }
 }
 
 
 impl CANAdaptor for DummyAdaptor {
-    fn receive(&mut self) -> Result<CANMessage, &'static str> {
-        println!("DummyAdaptor: Waiting");
-        thread::sleep(time::Duration::from_secs(5));
-        let message = CANMessage::with_content(0x01, 0x01, &[0xCA, 0xFE]);
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CANMessage>, CANError> {
+        let now = Instant::now();
+        if now + timeout < self.next_message_at {
+            thread::sleep(timeout);
+            return Ok(None);
+        }
+        thread::sleep(self.next_message_at.saturating_duration_since(now));
+        self.next_message_at += Duration::from_secs(5);
+        let message = CANMessage::with_content(0x01, &[0xCA, 0xFE]);
         println!("DummyAdaptor: Pretending to receive message {}", message);
-        Ok(message)
+        Ok(Some(message))
     }
 
-    fn send(&mut self, message: &CANMessage) -> Result<(), &'static str> {
+    fn send(&mut self, message: &CANMessage) -> Result<(), CANError> {
         println!("DummyAdaptor: Pretending to send message {}", message);
         Ok(())
     }
 }
-
-
@@ -1,5 +1,10 @@
-use std::{fmt, mem};
+use std::{error, fmt};
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeStruct;
 use serde_derive::*;
+use serde_json::Value;
 
 #[cfg(feature = "dummy")]
 pub mod dummy;
@@ -7,60 +12,354 @@ pub mod dummy;
 pub mod peak;
 #[cfg(feature = "pcan")]
 pub mod pcbusb;
+#[cfg(feature = "socketcan")]
+pub mod socketcan;
+#[cfg(feature = "socketcan")]
+pub mod afcan;
+pub mod virtualbus;
 
 
-#[repr(C)]  // TODO: this is here because of the Peak library; let's see what happens on Linux...
-#[derive(Debug, Copy, Clone, Serialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FrameFormat {
+    #[serde(rename = "standard")]  Standard,  // 11 bit identifier
+    #[serde(rename = "extended")]  Extended,  // 29 bit identifier
+}
+
+pub const MAX_CLASSIC_LENGTH: usize = 8;
+pub const MAX_FD_LENGTH: usize = 64;
+
+// data length for each DLC value of a CAN FD frame; classic frames stop at 8 bytes
+const FD_DLC_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+pub fn dlc_to_length(dlc: u8) -> usize {
+    FD_DLC_LENGTHS[(dlc & 0x0F) as usize]
+}
+
+// returns the smallest DLC that can hold the given number of bytes
+pub fn length_to_dlc(length: usize) -> u8 {
+    FD_DLC_LENGTHS.iter().position(|&l| l >= length).unwrap_or(15) as u8
+}
+
+
+#[derive(Debug, Copy, Clone)]
 pub struct CANMessage {
-    pub id: u64,  // TODO: this is u64 because of the Peak library...
-    #[serde(rename = "type")]
-    pub message_type: u8,
+    pub id: u32,
+    pub format: FrameFormat,
+    pub rtr: bool,
+    pub error: bool,
+    pub fd: bool,
+    pub brs: bool,  // bit rate switch, FD only
+    pub esi: bool,  // error state indicator, FD only
     pub length: u8,
-    pub data: [u8; 8],
+    pub data: [u8; MAX_FD_LENGTH],
+    pub timestamp: Option<u64>,  // in microseconds, see utils::monotonic_micros()
 }
 
 impl CANMessage {
     pub fn new() -> CANMessage {
-        unsafe { mem::zeroed() }
+        CANMessage {
+            id: 0, format: FrameFormat::Standard, rtr: false, error: false,
+            fd: false, brs: false, esi: false, length: 0, data: [0; MAX_FD_LENGTH], timestamp: None
+        }
     }
 
-    pub fn with_content(id: u64, message_type: u8, data: &[u8]) -> CANMessage {
+    pub fn with_content(id: u32, data: &[u8]) -> CANMessage {
         let mut m = CANMessage::new();
         m.id = id;
-        m.message_type = message_type;
-        m.length = data.len() as u8;
-        for i in 0..data.len() {
-            m.data[i] = data[i];
-        }
+        m.set_data(data);
+        m
+    }
+
+    pub fn with_fd_content(id: u32, data: &[u8]) -> CANMessage {
+        let mut m = CANMessage::new();
+        m.id = id;
+        m.fd = true;
+        m.set_data(data);
         m
     }
+
+    pub fn is_extended(&self) -> bool {
+        self.format == FrameFormat::Extended
+    }
+
+    pub fn max_length(&self) -> usize {
+        if self.fd { MAX_FD_LENGTH } else { MAX_CLASSIC_LENGTH }
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.data[..(self.length as usize)]
+    }
+
+    // sets the data and the length; FD frames are padded with zeros up to the next length
+    // that can be expressed with a DLC, overlong data is truncated
+    pub fn set_data(&mut self, data: &[u8]) {
+        let count = data.len().min(self.max_length());
+        self.data = [0; MAX_FD_LENGTH];
+        self.data[..count].copy_from_slice(&data[..count]);
+        self.length = if self.fd { dlc_to_length(length_to_dlc(count)) as u8 } else { count as u8 };
+    }
+}
+
+impl Serialize for CANMessage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut s = serializer.serialize_struct("CANMessage", 10)?;
+        s.serialize_field("id", &self.id)?;
+        s.serialize_field("format", &self.format)?;
+        s.serialize_field("rtr", &self.rtr)?;
+        s.serialize_field("error", &self.error)?;
+        s.serialize_field("fd", &self.fd)?;
+        s.serialize_field("brs", &self.brs)?;
+        s.serialize_field("esi", &self.esi)?;
+        s.serialize_field("length", &self.length)?;
+        s.serialize_field("data", self.payload())?;
+        if let Some(timestamp) = self.timestamp {
+            s.serialize_field("timestamp", &timestamp)?;
+        }
+        s.end()
+    }
 }
 
 impl fmt::Display for CANMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut data_str = String::new();
-        for i in 0..(self.length as usize) {
-            data_str.push_str(&format!("{:02X} ", self.data[i]));
+        for b in self.payload() {
+            data_str.push_str(&format!("{:02X} ", b));
+        }
+        let id_str = if self.is_extended() { format!("{:08X}", self.id) } else { format!("{:04X}", self.id) };
+        let mut flags_str = String::new();
+        for (flag, name) in &[(self.rtr, "RTR"), (self.error, "ERR"), (self.fd, "FD"), (self.brs, "BRS"), (self.esi, "ESI")] {
+            if *flag {
+                flags_str.push(' ');
+                flags_str.push_str(name);
+            }
         }
-        write!(f, "ID:{:04X} LEN:{:1X} DATA: {}", self.id, self.length, data_str)
+        write!(f, "ID:{}{} LEN:{:1X} DATA: {}", id_str, flags_str, self.length, data_str)
     }
 }
 
 
+// The kind of a frame as used in predicates and response templates. Fields that are not
+// specified match frames of any kind, or, in a response, are left at their defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FrameKind {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<FrameFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rtr: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fd: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub esi: Option<bool>,
+}
+
+impl FrameKind {
+    pub fn is_empty(&self) -> bool {
+        self.format.is_none() && self.rtr.is_none() && self.error.is_none()
+            && self.fd.is_none() && self.brs.is_none() && self.esi.is_none()
+    }
+
+    // bit rate switch and error state indicator only exist on FD frames, so they imply FD
+    pub fn is_fd(&self) -> bool {
+        self.fd == Some(true) || self.brs == Some(true) || self.esi == Some(true)
+    }
+
+    pub fn matches(&self, message: &CANMessage) -> bool {
+        self.format.is_none_or(|f| f == message.format)
+            && self.rtr.is_none_or(|r| r == message.rtr)
+            && self.error.is_none_or(|e| e == message.error)
+            && self.fd.is_none_or(|v| v == message.fd)
+            && self.brs.is_none_or(|v| v == message.brs)
+            && self.esi.is_none_or(|v| v == message.esi)
+    }
+
+    pub fn apply_to(&self, message: &mut CANMessage) {
+        if let Some(format) = self.format {
+            message.format = format;
+        }
+        if let Some(rtr) = self.rtr {
+            message.rtr = rtr;
+        }
+        if let Some(error) = self.error {
+            message.error = error;
+        }
+        if let Some(fd) = self.fd {
+            message.fd = fd;
+        }
+        if let Some(brs) = self.brs {
+            message.brs = brs;
+        }
+        if let Some(esi) = self.esi {
+            message.esi = esi;
+        }
+        if self.is_fd() {
+            message.fd = true;
+        }
+    }
+}
+
+
+// How serious an error returned by an adaptor is. After a transient error the adaptor can be used
+// again, while after a bus-off error or when the device is gone it has to be re-initialized.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum ErrorClass {
+    #[serde(rename = "transient")]   Transient,
+    #[serde(rename = "busOff")]      BusOff,
+    #[serde(rename = "deviceGone")]  DeviceGone,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum ErrorKind {
+    #[serde(rename = "busOff")]         BusOff,
+    // an error counter of the CAN controller reached the warning or passive limit
+    #[serde(rename = "busError")]       BusError,
+    // received messages were lost because they were not read in time
+    #[serde(rename = "overrun")]        Overrun,
+    #[serde(rename = "queueFull")]      QueueFull,
+    #[serde(rename = "noDriver")]       NoDriver,
+    #[serde(rename = "deviceGone")]     DeviceGone,
+    #[serde(rename = "invalidConfig")]  InvalidConfig,
+    #[serde(rename = "unsupported")]    Unsupported,
+    #[serde(rename = "other")]          Other,
+}
+
+// An error returned by an adaptor. Where available, the code is the error code of the underlying
+// driver or system call, e.g. the PCAN status or the errno of a socket call.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CANError {
+    pub kind: ErrorKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<u64>,
+    pub message: &'static str,
+}
+
+impl CANError {
+    pub fn new(kind: ErrorKind, message: &'static str) -> CANError {
+        CANError { kind, code: None, message }
+    }
+
+    pub fn with_code(kind: ErrorKind, code: u64, message: &'static str) -> CANError {
+        CANError { kind, code: Some(code), message }
+    }
+
+    pub fn class(&self) -> ErrorClass {
+        match self.kind {
+            ErrorKind::BusOff => ErrorClass::BusOff,
+            ErrorKind::NoDriver | ErrorKind::DeviceGone => ErrorClass::DeviceGone,
+            _ => ErrorClass::Transient,
+        }
+    }
+}
+
+impl fmt::Display for CANError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code {
+            Some(code) => write!(f, "{} ({:?}, code 0x{:x})", self.message, self.kind, code),
+            None => write!(f, "{} ({:?})", self.message, self.kind),
+        }
+    }
+}
+
+impl error::Error for CANError {}
+
+
 pub trait CANAdaptor {
-    fn receive(&mut self) -> Result<CANMessage, &'static str>;
-    fn send(&mut self, message: &CANMessage) -> Result<(), &'static str>;
+    // returns Ok(None) when no message was received before the timeout expired
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CANMessage>, CANError>;
+    fn send(&mut self, message: &CANMessage) -> Result<(), CANError>;
 }
 
 
-#[cfg(feature = "pcan")]
-pub fn create_adaptor() -> Result<Box<CANAdaptor>, &'static str> {
-    self::peak::PeakAdaptor::new()
+pub type AdaptorFactory = fn(&AdaptorConfig) -> Result<Box<dyn CANAdaptor>, CANError>;
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdaptorConfig {
+    #[serde(rename = "type")]
+    pub adaptor_type: String,
+    #[serde(flatten)]
+    pub options: HashMap<String, Value>,
 }
 
-#[cfg(feature = "dummy")]
-pub fn create_adaptor() -> Result<Box<CANAdaptor>, &'static str> {
-    self::dummy::DummyAdaptor::new()
+impl AdaptorConfig {
+    pub fn new(adaptor_type: &str) -> AdaptorConfig {
+        AdaptorConfig { adaptor_type: adaptor_type.to_string(), options: HashMap::new() }
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) {
+        self.options.insert(name.to_string(), Value::String(value.to_string()));
+    }
+
+    pub fn option(&self, name: &str) -> Option<String> {
+        match self.options.get(name) {
+            Some(Value::String(s)) => Some(s.clone()),
+            Some(Value::Null) | None => None,
+            Some(other) => Some(other.to_string()),
+        }
+    }
+
+    pub fn option_or(&self, name: &str, default: &str) -> String {
+        self.option(name).unwrap_or_else(|| default.to_string())
+    }
+}
+
+
+pub struct AdaptorRegistry {
+    factories: Vec<(String, AdaptorFactory)>,
+}
+
+impl AdaptorRegistry {
+    pub fn new() -> AdaptorRegistry {
+        AdaptorRegistry { factories: Vec::new() }
+    }
+
+    // the order matters: the first adaptor registered is used when no adaptor is specified,
+    // and we prefer real hardware over the adaptors that don't need any
+    pub fn with_builtin_adaptors() -> AdaptorRegistry {
+        let mut registry = AdaptorRegistry::new();
+        #[cfg(feature = "pcan")]
+        registry.register("pcan", self::peak::PeakAdaptor::create);
+        #[cfg(feature = "socketcan")]
+        registry.register("socketcan", self::socketcan::SocketCANAdaptor::create);
+        #[cfg(feature = "dummy")]
+        registry.register("dummy", self::dummy::DummyAdaptor::create);
+        registry.register("virtual", self::virtualbus::VirtualAdaptor::create);
+        registry
+    }
+
+    pub fn register(&mut self, name: &str, factory: AdaptorFactory) {
+        self.factories.retain(|(n, _)| n != name);
+        self.factories.push((name.to_string(), factory));
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.factories.iter().map(|(n, _)| n.clone()).collect()
+    }
+
+    pub fn default_name(&self) -> Option<String> {
+        self.factories.first().map(|(n, _)| n.clone())
+    }
+
+    pub fn create(&self, config: &AdaptorConfig) -> Result<Box<dyn CANAdaptor>, CANError> {
+        match self.factories.iter().find(|(n, _)| *n == config.adaptor_type) {
+            Some((_, factory)) => factory(config),
+            None => Err(CANError::new(ErrorKind::InvalidConfig, "unknown CAN adaptor type or adaptor not included in this build"))
+        }
+    }
+}
+
+impl Default for AdaptorRegistry {
+    fn default() -> Self {
+        AdaptorRegistry::new()
+    }
+}
+
+
+pub fn create_adaptor(config: &AdaptorConfig) -> Result<Box<dyn CANAdaptor>, CANError> {
+    AdaptorRegistry::with_builtin_adaptors().create(config)
 }
 
 
@@ -70,11 +369,123 @@ mod tests {
 
     #[test]
     fn creates_message_with_content() {
-        let m = CANMessage::with_content(0x101, 7, &[0x20, 0x30]);
+        let m = CANMessage::with_content(0x101, &[0x20, 0x30]);
 
         assert_eq!(0x101, m.id);
-        assert_eq!(7, m.message_type);
+        assert_eq!(FrameFormat::Standard, m.format);
         assert_eq!(2, m.length);
-        assert_eq!([0x20, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], m.data);
+        assert_eq!([0x20, 0x30], m.payload());
+        assert_eq!([0x20, 0x30, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00], m.data[..8]);
+    }
+
+    #[test]
+    fn truncates_data_of_classic_frame_to_eight_bytes() {
+        let m = CANMessage::with_content(0x101, &[0x01; 12]);
+
+        assert_eq!(8, m.length);
+    }
+
+    #[test]
+    fn pads_fd_frame_to_next_valid_length() {
+        let m = CANMessage::with_fd_content(0x101, &[0x01; 10]);
+
+        assert_eq!(12, m.length);
+        assert_eq!([0x01, 0x01, 0x00, 0x00], m.payload()[8..]);
+    }
+
+    #[test]
+    fn maps_between_dlc_and_length() {
+        assert_eq!(8, dlc_to_length(8));
+        assert_eq!(12, dlc_to_length(9));
+        assert_eq!(64, dlc_to_length(15));
+        assert_eq!(9, length_to_dlc(9));
+        assert_eq!(13, length_to_dlc(32));
+        assert_eq!(15, length_to_dlc(64));
+    }
+
+    #[test]
+    fn serializes_only_used_data_bytes() {
+        let m = CANMessage::with_content(0x101, &[0x20, 0x30]);
+
+        let json = serde_json::to_value(&m).unwrap();
+
+        assert_eq!(2, json["data"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn frame_kind_matches_only_specified_fields() {
+        let kind: FrameKind = serde_json::from_str(r#"{ "format": "extended" }"#).unwrap();
+        let mut m = CANMessage::with_content(0x18DAF110, &[]);
+
+        assert_eq!(false, kind.matches(&m));
+        m.format = FrameFormat::Extended;
+        m.rtr = true;
+        assert_eq!(true, kind.matches(&m));
+    }
+
+    #[test]
+    fn frame_kind_sets_only_specified_fields() {
+        let kind: FrameKind = serde_json::from_str(r#"{ "rtr": true }"#).unwrap();
+        let mut m = CANMessage::new();
+        m.format = FrameFormat::Extended;
+        m.fd = true;
+        m.brs = true;
+
+        kind.apply_to(&mut m);
+
+        assert_eq!(FrameFormat::Extended, m.format);
+        assert_eq!(true, m.rtr);
+        assert!(m.fd && m.brs);
+    }
+
+    #[test]
+    fn frame_kind_with_bit_rate_switch_implies_fd() {
+        let kind: FrameKind = serde_json::from_str(r#"{ "brs": true }"#).unwrap();
+        let mut m = CANMessage::new();
+
+        kind.apply_to(&mut m);
+
+        assert!(m.fd && m.brs && !m.esi);
+    }
+
+    #[test]
+    fn returns_string_value_of_options() {
+        let config: AdaptorConfig = serde_json::from_str(r#"{ "type": "pcan", "channel": "usb2", "bitrate": 250000 }"#).unwrap();
+
+        assert_eq!("pcan", config.adaptor_type);
+        assert_eq!(Some("usb2".to_string()), config.option("channel"));
+        assert_eq!(Some("250000".to_string()), config.option("bitrate"));
+        assert_eq!(None, config.option("interface"));
+    }
+
+    #[test]
+    fn creates_adaptor_registered_under_name() {
+        let registry = AdaptorRegistry::with_builtin_adaptors();
+
+        assert!(registry.names().contains(&"virtual".to_string()));
+        assert!(registry.create(&AdaptorConfig::new("virtual")).is_ok());
+    }
+
+    #[test]
+    fn fails_to_create_adaptor_with_unknown_name() {
+        let registry = AdaptorRegistry::with_builtin_adaptors();
+
+        let result = registry.create(&AdaptorConfig::new("no-such-adaptor"));
+
+        assert_eq!(ErrorKind::InvalidConfig, result.err().unwrap().kind);
+    }
+
+    #[test]
+    fn derives_error_class_from_kind() {
+        assert_eq!(ErrorClass::BusOff, CANError::new(ErrorKind::BusOff, "").class());
+        assert_eq!(ErrorClass::DeviceGone, CANError::new(ErrorKind::NoDriver, "").class());
+        assert_eq!(ErrorClass::Transient, CANError::new(ErrorKind::QueueFull, "").class());
+    }
+
+    #[test]
+    fn includes_code_in_error_description() {
+        let error = CANError::with_code(ErrorKind::BusOff, 0x10, "CAN_Read error");
+
+        assert_eq!("CAN_Read error (BusOff, code 0x10)", error.to_string());
     }
 }
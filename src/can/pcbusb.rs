@@ -1,4 +1,4 @@
-use crate::can::CANMessage;
+use libc::c_char;
 
 
 /* functions defined in libPCBUSB, which we use on the Mac */
@@ -8,12 +8,33 @@ extern "C" {
     pub fn CAN_Initialize(channel: u16, bitrate: u16, hw_type: u8, io_port: u64, interrupt: u16) -> u64;
     pub fn CAN_Uninitialize(channel: u16) -> u64;
     pub fn CAN_GetValue(channel: u16, parameter: u8, buffer: &i32, buffer_len: usize) -> u64;
-    pub fn CAN_Read(channel: u16, message_buffer: *mut CANMessage, timestamp_buffer: *mut CANTimestamp) -> u64;
-    pub fn CAN_Write(channel: u16, message_buffer: *const CANMessage) -> u64;
+    pub fn CAN_Read(channel: u16, message_buffer: *mut TPCANMsg, timestamp_buffer: *mut CANTimestamp) -> u64;
+    pub fn CAN_Write(channel: u16, message_buffer: *const TPCANMsg) -> u64;
+    pub fn CAN_InitializeFD(channel: u16, bitrate_fd: *const c_char) -> u64;
+    pub fn CAN_ReadFD(channel: u16, message_buffer: *mut TPCANMsgFD, timestamp_buffer: *mut u64) -> u64;
+    pub fn CAN_WriteFD(channel: u16, message_buffer: *const TPCANMsgFD) -> u64;
 }
 
 
-/* types used for arguments to functions (note we use CANMessage from module, too) */
+/* types used for arguments to functions */
+
+#[repr(C)]
+pub struct TPCANMsg
+{
+    pub id: u64,
+    pub msgtype: u8,
+    pub len: u8,
+    pub data: [u8; 8],
+}
+
+#[repr(C)]
+pub struct TPCANMsgFD
+{
+    pub id: u64,
+    pub msgtype: u8,
+    pub dlc: u8,
+    pub data: [u8; 64],
+}
 
 #[repr(C)]
 pub struct CANTimestamp
@@ -28,9 +49,25 @@ pub struct CANTimestamp
 
 pub const PCAN_NONEBUS: u16 = 0x00;
 pub const PCAN_USBBUS1: u16 = 0x51;
+pub const PCAN_USBBUS2: u16 = 0x52;
+pub const PCAN_USBBUS3: u16 = 0x53;
+pub const PCAN_USBBUS4: u16 = 0x54;
+pub const PCAN_USBBUS5: u16 = 0x55;
+pub const PCAN_USBBUS6: u16 = 0x56;
+pub const PCAN_USBBUS7: u16 = 0x57;
+pub const PCAN_USBBUS8: u16 = 0x58;
 
 pub const PCAN_RECEIVE_EVENT: u8 = 0x03; // PCAN receive event handler parameter
 
+pub const PCAN_MESSAGE_STANDARD : u8 = 0x00; // standard data frame (11-bit ID)
+pub const PCAN_MESSAGE_RTR      : u8 = 0x01; // remote request frame
+pub const PCAN_MESSAGE_EXTENDED : u8 = 0x02; // extended data frame (CAN 2.0B, 29-bit ID)
+pub const PCAN_MESSAGE_FD       : u8 = 0x04; // CAN FD frame
+pub const PCAN_MESSAGE_BRS      : u8 = 0x08; // CAN FD frame with bit rate switch
+pub const PCAN_MESSAGE_ESI      : u8 = 0x10; // CAN FD frame with error state indicator set
+pub const PCAN_MESSAGE_ERRFRAME : u8 = 0x40; // error frame
+pub const PCAN_MESSAGE_STATUS   : u8 = 0x80; // PCAN status message
+
 pub const PCAN_BAUD_1M  : u16 = 0x0014;
 pub const PCAN_BAUD_800K: u16 = 0x0016;
 pub const PCAN_BAUD_500K: u16 = 0x001C;
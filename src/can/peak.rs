@@ -1,13 +1,98 @@
 use std::{fmt, mem, ptr};
-use libc::{select, fd_set, FD_ZERO, FD_SET};
-use crate::can::{CANMessage, CANAdaptor};
+use std::ffi::CString;
+use std::time::Duration;
+use libc::{select, fd_set, timeval, time_t, suseconds_t, FD_ZERO, FD_SET};
+use crate::can::{CANMessage, CANAdaptor, CANError, ErrorKind, AdaptorConfig, FrameFormat, MAX_CLASSIC_LENGTH, dlc_to_length, length_to_dlc};
 use crate::can::pcbusb::*;
+use crate::utils;
 
 
 impl CANTimestamp {
     pub fn new() -> CANTimestamp {
         unsafe { mem::zeroed() }
     }
+
+    pub fn as_micros(&self) -> u64 {
+        let millis = (self.millis & 0xFFFFFFFF) + ((self.millis_overflow as u64) << 32);
+        millis * 1000 + self.micros as u64
+    }
+}
+
+fn msgtype_from_message(message: &CANMessage) -> u8 {
+    let mut msgtype = PCAN_MESSAGE_STANDARD;
+    for (flag, bit) in &[(message.is_extended(), PCAN_MESSAGE_EXTENDED), (message.rtr, PCAN_MESSAGE_RTR),
+                         (message.error, PCAN_MESSAGE_ERRFRAME), (message.fd, PCAN_MESSAGE_FD),
+                         (message.brs, PCAN_MESSAGE_BRS), (message.esi, PCAN_MESSAGE_ESI)] {
+        if *flag {
+            msgtype |= bit;
+        }
+    }
+    msgtype
+}
+
+fn message_from_msgtype(id: u64, msgtype: u8, data: &[u8]) -> CANMessage {
+    let mut message = CANMessage::new();
+    message.id = id as u32;
+    if msgtype & PCAN_MESSAGE_EXTENDED != 0 {
+        message.format = FrameFormat::Extended;
+    }
+    message.rtr = msgtype & PCAN_MESSAGE_RTR != 0;
+    message.error = msgtype & PCAN_MESSAGE_ERRFRAME != 0;
+    message.fd = msgtype & PCAN_MESSAGE_FD != 0;
+    message.brs = msgtype & PCAN_MESSAGE_BRS != 0;
+    message.esi = msgtype & PCAN_MESSAGE_ESI != 0;
+    message.set_data(data);
+    message
+}
+
+impl Default for TPCANMsg {
+    fn default() -> Self {
+        TPCANMsg::new()
+    }
+}
+
+impl TPCANMsg {
+    pub fn new() -> TPCANMsg {
+        unsafe { mem::zeroed() }
+    }
+
+    pub fn from_message(message: &CANMessage) -> TPCANMsg {
+        let mut msg = TPCANMsg::new();
+        msg.id = message.id as u64;
+        msg.msgtype = msgtype_from_message(message);
+        msg.len = message.length.min(MAX_CLASSIC_LENGTH as u8);
+        msg.data.copy_from_slice(&message.data[..MAX_CLASSIC_LENGTH]);
+        msg
+    }
+
+    pub fn to_message(&self) -> CANMessage {
+        message_from_msgtype(self.id, self.msgtype, &self.data[..(self.len.min(8) as usize)])
+    }
+}
+
+impl Default for TPCANMsgFD {
+    fn default() -> Self {
+        TPCANMsgFD::new()
+    }
+}
+
+impl TPCANMsgFD {
+    pub fn new() -> TPCANMsgFD {
+        unsafe { mem::zeroed() }
+    }
+
+    pub fn from_message(message: &CANMessage) -> TPCANMsgFD {
+        let mut msg = TPCANMsgFD::new();
+        msg.id = message.id as u64;
+        msg.msgtype = msgtype_from_message(message);
+        msg.dlc = length_to_dlc(message.length as usize);
+        msg.data = message.data;
+        msg
+    }
+
+    pub fn to_message(&self) -> CANMessage {
+        message_from_msgtype(self.id, self.msgtype, &self.data[..dlc_to_length(self.dlc)])
+    }
 }
 
 impl fmt::Display for CANTimestamp {
@@ -18,30 +103,55 @@ impl fmt::Display for CANTimestamp {
 
 
 pub struct PeakAdaptor {
+    channel: u16,
     fd: i32,
+    fd_mode: bool,
+    clock_offset: Option<i64>,
 }
 
+// 500 kbit/s nominal and 2 Mbit/s data bitrate with the 80 MHz clock of the PCAN-USB FD
+const DEFAULT_FD_BITRATE: &str = "f_clock_mhz=80, nom_brp=2, nom_tseg1=63, nom_tseg2=16, nom_sjw=16, \
+                                  data_brp=2, data_tseg1=15, data_tseg2=4, data_sjw=4";
+
 
 impl PeakAdaptor {
-    pub fn new() -> Result<Box<CANAdaptor>, &'static str> {
-        let status = unsafe { CAN_Initialize(PCAN_USBBUS1, PCAN_BAUD_500K, 0, 0, 0) };
+    pub fn create(config: &AdaptorConfig) -> Result<Box<dyn CANAdaptor>, CANError> {
+        let channel = channel_from_name(&config.option_or("channel", "usb1"))?;
+        let adaptor = if config.option_or("fd", "false") == "true" {
+            PeakAdaptor::new_fd(channel, &config.option_or("fdBitrate", DEFAULT_FD_BITRATE))?
+        } else {
+            PeakAdaptor::new(channel, baud_from_bitrate(&config.option_or("bitrate", "500000"))?)?
+        };
+        Ok(Box::new(adaptor))
+    }
+
+    pub fn new(channel: u16, bitrate: u16) -> Result<PeakAdaptor, CANError> {
+        let status = unsafe { CAN_Initialize(channel, bitrate, 0, 0, 0) };
         log(&format!("Initialized CAN device (0x{:x})", status));
         if status != PCAN_ERROR_OK {
-            return Err("CAN_Initialize error");
+            return Err(error_from_status(status, "CAN_Initialize error"));
         }
-        let fd: i32 = 0;
-        let status = unsafe { CAN_GetValue(PCAN_USBBUS1, PCAN_RECEIVE_EVENT, &fd, mem::size_of::<i32>()) };
-        log(&format!("Got file descriptor for CAN device (0x{:x})", status));
+        PeakAdaptor::with_receive_event(channel, false)
+    }
+
+    pub fn new_fd(channel: u16, bitrate_fd: &str) -> Result<PeakAdaptor, CANError> {
+        let bitrate_fd = CString::new(bitrate_fd).map_err(|_| CANError::new(ErrorKind::InvalidConfig, "invalid PCAN FD bitrate string"))?;
+        let status = unsafe { CAN_InitializeFD(channel, bitrate_fd.as_ptr()) };
+        log(&format!("Initialized CAN device in FD mode (0x{:x})", status));
         if status != PCAN_ERROR_OK {
-            return Err("CAN_GetValue error when retrieving file descriptor for reading");
+            return Err(error_from_status(status, "CAN_InitializeFD error"));
         }
-        Ok(Box::new(PeakAdaptor { fd }))
+        PeakAdaptor::with_receive_event(channel, true)
     }
 
-    pub fn drop(&mut self) {
-        let status = unsafe { CAN_Uninitialize(PCAN_NONEBUS) };
-        log(&format!("Uninitialized all can devices (0x{:x})", status));
-        // no return value, if it fails, it fails...
+    fn with_receive_event(channel: u16, fd_mode: bool) -> Result<PeakAdaptor, CANError> {
+        let fd: i32 = 0;
+        let status = unsafe { CAN_GetValue(channel, PCAN_RECEIVE_EVENT, &fd, mem::size_of::<i32>()) };
+        log(&format!("Got file descriptor for CAN device (0x{:x})", status));
+        if status != PCAN_ERROR_OK {
+            return Err(error_from_status(status, "CAN_GetValue error when retrieving file descriptor for reading"));
+        }
+        Ok(PeakAdaptor { channel, fd, fd_mode, clock_offset: None })
     }
 
     fn get_fd_set(&self) -> fd_set {
@@ -53,29 +163,87 @@ impl PeakAdaptor {
         fds
     }
 
+    fn read(&mut self) -> Result<Option<CANMessage>, CANError> {
+        let mut msg = TPCANMsg::new();
+        let mut timestamp = CANTimestamp::new();
+        let status = unsafe { CAN_Read(self.channel, &mut msg, &mut timestamp) };
+        if status != PCAN_ERROR_OK {
+            return Err(error_from_status(status, "CAN_Read error"));
+        }
+        if msg.msgtype & PCAN_MESSAGE_STATUS != 0 {
+            return Ok(None);
+        }
+        let mut message = msg.to_message();
+        message.timestamp = Some(self.host_time(timestamp.as_micros()));
+        Ok(Some(message))
+    }
+
+    fn read_fd(&mut self) -> Result<Option<CANMessage>, CANError> {
+        let mut msg = TPCANMsgFD::new();
+        let mut timestamp: u64 = 0;
+        let status = unsafe { CAN_ReadFD(self.channel, &mut msg, &mut timestamp) };
+        if status != PCAN_ERROR_OK {
+            return Err(error_from_status(status, "CAN_ReadFD error"));
+        }
+        if msg.msgtype & PCAN_MESSAGE_STATUS != 0 {
+            return Ok(None);
+        }
+        let mut message = msg.to_message();
+        message.timestamp = Some(self.host_time(timestamp));
+        Ok(Some(message))
+    }
+
+    // The hardware timestamps have a better resolution than the host clock but they count from
+    // when the device was started. The offset to the host clock is taken from the first message
+    // so that timestamps of received and sent messages can be compared.
+    fn host_time(&mut self, hw_micros: u64) -> u64 {
+        let offset = *self.clock_offset.get_or_insert_with(|| utils::monotonic_micros() as i64 - hw_micros as i64);
+        (hw_micros as i64 + offset).max(0) as u64
+    }
+
+}
+
+
+impl Drop for PeakAdaptor {
+    fn drop(&mut self) {
+        let status = unsafe { CAN_Uninitialize(self.channel) };
+        log(&format!("Uninitialized CAN device (0x{:x})", status));
+        // no return value, if it fails, it fails...
+    }
 }
 
 
 impl CANAdaptor for PeakAdaptor {
-    fn receive(&mut self) -> Result<CANMessage, &'static str> {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CANMessage>, CANError> {
         let mut fds = self.get_fd_set();
-        if unsafe { select(self.fd + 1, &mut fds, ptr::null_mut(), ptr::null_mut(), ptr::null_mut()) } == 0 {
-            return Err("select returned 0 (timeout?)");
+        let mut tv = timeval { tv_sec: timeout.as_secs() as time_t, tv_usec: timeout.subsec_micros() as suseconds_t };
+        let status = unsafe { select(self.fd + 1, &mut fds, ptr::null_mut(), ptr::null_mut(), &mut tv) };
+        if status < 0 {
+            return Err(CANError::new(ErrorKind::DeviceGone, "select error on CAN device file descriptor"));
         }
-        let mut message = CANMessage::new();
-        let mut timestamp = CANTimestamp::new();
-        let status = unsafe { CAN_Read(PCAN_USBBUS1, &mut message, &mut timestamp) };
-        if status != PCAN_ERROR_OK {
-            return Err("CAN_Read error"); // TODO: maybe include error code
+        if status == 0 {
+            return Ok(None);
+        }
+        let message = if self.fd_mode { self.read_fd()? } else { self.read()? };
+        if let Some(message) = &message {
+            log(&format!("<< {}", message));
         }
-        log(&format!("<< {}", &message));
-        Ok(message.clone())
+        Ok(message)
     }
 
-    fn send(&mut self, message: &CANMessage) -> Result<(), &'static str> {
-        let status = unsafe { CAN_Write(PCAN_USBBUS1, message) };
+    fn send(&mut self, message: &CANMessage) -> Result<(), CANError> {
+        let status = if self.fd_mode {
+            let msg = TPCANMsgFD::from_message(message);
+            unsafe { CAN_WriteFD(self.channel, &msg) }
+        } else {
+            if message.fd {
+                return Err(CANError::new(ErrorKind::Unsupported, "cannot send FD frame; adaptor is not in FD mode"));
+            }
+            let msg = TPCANMsg::from_message(message);
+            unsafe { CAN_Write(self.channel, &msg) }
+        };
         if status != PCAN_ERROR_OK {
-            return Err("CAN_Write error"); // TODO: maybe include error code
+            return Err(error_from_status(status, "CAN_Write error"));
         }
         log(&format!(">> {}", &message));
         Ok(())
@@ -84,6 +252,66 @@ impl CANAdaptor for PeakAdaptor {
 }
 
 
+// The status can combine several errors; the most serious one determines the kind. The handle
+// errors are not single bits, so they have to be compared after masking.
+fn error_from_status(status: u64, message: &'static str) -> CANError {
+    let handle_error = status & PCAN_ERROR_ILLHANDLE;
+    let kind = if status & PCAN_ERROR_BUSOFF != 0 {
+        ErrorKind::BusOff
+    } else if status & PCAN_ERROR_NODRIVER != 0 {
+        ErrorKind::NoDriver
+    } else if handle_error == PCAN_ERROR_ILLHW || handle_error == PCAN_ERROR_ILLNET || handle_error == PCAN_ERROR_ILLCLIENT
+            || status & (PCAN_ERROR_REGTEST | PCAN_ERROR_INITIALIZE) != 0 {
+        ErrorKind::DeviceGone
+    } else if status & (PCAN_ERROR_XMTFULL | PCAN_ERROR_QXMTFULL) != 0 {
+        ErrorKind::QueueFull
+    } else if status & (PCAN_ERROR_OVERRUN | PCAN_ERROR_QOVERRUN) != 0 {
+        ErrorKind::Overrun
+    } else if status & (PCAN_ERROR_BUSLIGHT | PCAN_ERROR_BUSHEAVY | PCAN_ERROR_BUSPASSIVE) != 0 {
+        ErrorKind::BusError
+    } else if status & (PCAN_ERROR_ILLPARAMTYPE | PCAN_ERROR_ILLPARAMVAL) != 0 {
+        ErrorKind::InvalidConfig
+    } else {
+        ErrorKind::Other
+    };
+    CANError::with_code(kind, status, message)
+}
+
+fn channel_from_name(name: &str) -> Result<u16, CANError> {
+    match name.to_lowercase().as_str() {
+        "usb1" => Ok(PCAN_USBBUS1),
+        "usb2" => Ok(PCAN_USBBUS2),
+        "usb3" => Ok(PCAN_USBBUS3),
+        "usb4" => Ok(PCAN_USBBUS4),
+        "usb5" => Ok(PCAN_USBBUS5),
+        "usb6" => Ok(PCAN_USBBUS6),
+        "usb7" => Ok(PCAN_USBBUS7),
+        "usb8" => Ok(PCAN_USBBUS8),
+        _ => Err(CANError::new(ErrorKind::InvalidConfig, "invalid PCAN channel; expected usb1 to usb8"))
+    }
+}
+
+fn baud_from_bitrate(bitrate: &str) -> Result<u16, CANError> {
+    match bitrate {
+        "1000000" => Ok(PCAN_BAUD_1M),
+        "800000" => Ok(PCAN_BAUD_800K),
+        "500000" => Ok(PCAN_BAUD_500K),
+        "250000" => Ok(PCAN_BAUD_250K),
+        "125000" => Ok(PCAN_BAUD_125K),
+        "100000" => Ok(PCAN_BAUD_100K),
+        "95000" => Ok(PCAN_BAUD_95K),
+        "83000" => Ok(PCAN_BAUD_83K),
+        "50000" => Ok(PCAN_BAUD_50K),
+        "47000" => Ok(PCAN_BAUD_47K),
+        "33000" => Ok(PCAN_BAUD_33K),
+        "20000" => Ok(PCAN_BAUD_20K),
+        "10000" => Ok(PCAN_BAUD_10K),
+        "5000" => Ok(PCAN_BAUD_5K),
+        _ => Err(CANError::new(ErrorKind::InvalidConfig, "unsupported PCAN bitrate"))
+    }
+}
+
+
 fn log(message: &str) {
     println!("{}", message);
 }
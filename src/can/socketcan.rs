@@ -0,0 +1,282 @@
+use std::ffi::CString;
+use std::io;
+use std::{mem, ptr};
+use std::time::Duration;
+use libc::{socket, bind, recvmsg, write, close, poll, setsockopt, if_nametoindex, sockaddr, socklen_t, pollfd, c_int, c_void, iovec, msghdr, timeval,
+           CMSG_FIRSTHDR, CMSG_NXTHDR, CMSG_DATA, CMSG_SPACE, AF_CAN, PF_CAN, SOCK_RAW, POLLIN, SOL_SOCKET, SO_TIMESTAMP, SCM_TIMESTAMP,
+           ENETDOWN, ENODEV, ENXIO, ENOBUFS};
+use crate::can::{CANMessage, CANAdaptor, CANError, ErrorKind, AdaptorConfig, FrameFormat, MAX_FD_LENGTH};
+use crate::can::afcan::*;
+use crate::utils;
+
+
+pub struct SocketCANAdaptor {
+    fd: i32,
+    fd_frames: bool,
+    clock_offset: Option<i64>,
+}
+
+
+impl SocketCANAdaptor {
+    pub fn create(config: &AdaptorConfig) -> Result<Box<dyn CANAdaptor>, CANError> {
+        if config.option("bitrate").is_some() {
+            return Err(CANError::new(ErrorKind::InvalidConfig, "bitrate of SocketCAN interfaces must be set with ip link"));
+        }
+        let adaptor = SocketCANAdaptor::new(&config.option_or("interface", "can0"), config.option_or("fd", "false") == "true")?;
+        Ok(Box::new(adaptor))
+    }
+
+    pub fn new(interface: &str, fd_frames: bool) -> Result<SocketCANAdaptor, CANError> {
+        let name = CString::new(interface).map_err(|_| CANError::new(ErrorKind::InvalidConfig, "invalid CAN interface name"))?;
+        let ifindex = unsafe { if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(CANError::new(ErrorKind::DeviceGone, "CAN interface not found"));
+        }
+        let fd = unsafe { socket(PF_CAN, SOCK_RAW, CAN_RAW) };
+        if fd < 0 {
+            return Err(error_from_errno("failed to open CAN_RAW socket"));
+        }
+        let addr = sockaddr_can { can_family: AF_CAN as u16, can_ifindex: ifindex as i32, rx_id: 0, tx_id: 0 };
+        let status = unsafe {
+            bind(fd, &addr as *const sockaddr_can as *const sockaddr, mem::size_of::<sockaddr_can>() as socklen_t)
+        };
+        if status < 0 {
+            let error = error_from_errno("failed to bind CAN_RAW socket to interface");
+            unsafe { close(fd) };
+            return Err(error);
+        }
+        if fd_frames {
+            let enable: c_int = 1;
+            let status = unsafe {
+                setsockopt(fd, SOL_CAN_RAW, CAN_RAW_FD_FRAMES, &enable as *const c_int as *const c_void, mem::size_of::<c_int>() as socklen_t)
+            };
+            if status < 0 {
+                unsafe { close(fd) };
+                return Err(CANError::new(ErrorKind::Unsupported, "failed to enable FD frames on CAN_RAW socket; does the interface support CAN FD?"));
+            }
+        }
+        // without kernel timestamps messages are timestamped when they are read
+        let enable: c_int = 1;
+        let status = unsafe {
+            setsockopt(fd, SOL_SOCKET, SO_TIMESTAMP, &enable as *const c_int as *const c_void, mem::size_of::<c_int>() as socklen_t)
+        };
+        if status < 0 {
+            log("Failed to enable timestamps on CAN_RAW socket");
+        }
+        log(&format!("Bound CAN_RAW socket to interface {} (index {})", interface, ifindex));
+        Ok(SocketCANAdaptor { fd, fd_frames, clock_offset: None })
+    }
+
+    // Reads a frame and the time the kernel received it, which is on the realtime clock.
+    fn read_frame(&self, frame: &mut canfd_frame) -> Result<(usize, Option<u64>), CANError> {
+        let mut iov = iovec { iov_base: frame as *mut canfd_frame as *mut c_void, iov_len: CANFD_MTU };
+        let mut control = [0u64; 8];
+        let mut msg: msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut c_void;
+        msg.msg_controllen = unsafe { CMSG_SPACE(mem::size_of::<timeval>() as u32) } as _;
+        let count = unsafe { recvmsg(self.fd, &mut msg, 0) };
+        if count < 0 {
+            return Err(error_from_errno("read error on CAN_RAW socket"));
+        }
+        let mut timestamp = None;
+        let mut cmsg = unsafe { CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let header = unsafe { &*cmsg };
+            if header.cmsg_level == SOL_SOCKET && header.cmsg_type == SCM_TIMESTAMP {
+                let tv: timeval = unsafe { ptr::read_unaligned(CMSG_DATA(cmsg) as *const timeval) };
+                timestamp = Some(tv.tv_sec as u64 * 1_000_000 + tv.tv_usec as u64);
+            }
+            cmsg = unsafe { CMSG_NXTHDR(&msg, cmsg) };
+        }
+        Ok((count as usize, timestamp))
+    }
+
+    // The kernel timestamps are taken before Candouble reads the message, but they are on the
+    // realtime clock. As for the PCAN adaptor the offset to the host clock is taken from the first
+    // message, so that timestamps of received and sent messages can be compared.
+    fn host_time(&mut self, kernel_micros: u64) -> u64 {
+        let offset = *self.clock_offset.get_or_insert_with(|| utils::monotonic_micros() as i64 - kernel_micros as i64);
+        (kernel_micros as i64 + offset).max(0) as u64
+    }
+
+    fn message_from_frame(frame: &canfd_frame, is_fd: bool) -> CANMessage {
+        let mut message = CANMessage::new();
+        message.fd = is_fd;
+        message.set_data(&frame.data[..(frame.len as usize).min(MAX_FD_LENGTH)]);
+        if frame.can_id & CAN_EFF_FLAG != 0 {
+            message.id = frame.can_id & CAN_EFF_MASK;
+            message.format = FrameFormat::Extended;
+        } else {
+            message.id = frame.can_id & CAN_SFF_MASK;
+        }
+        message.rtr = frame.can_id & CAN_RTR_FLAG != 0;
+        message.error = frame.can_id & CAN_ERR_FLAG != 0;
+        if is_fd {
+            message.brs = frame.flags & CANFD_BRS != 0;
+            message.esi = frame.flags & CANFD_ESI != 0;
+        }
+        message
+    }
+
+    fn frame_from_message(message: &CANMessage) -> canfd_frame {
+        let mut frame: canfd_frame = unsafe { mem::zeroed() };
+        if message.is_extended() {
+            frame.can_id = (message.id & CAN_EFF_MASK) | CAN_EFF_FLAG;
+        } else {
+            frame.can_id = message.id & CAN_SFF_MASK;
+        }
+        if message.rtr {
+            frame.can_id |= CAN_RTR_FLAG;
+        }
+        if message.error {
+            frame.can_id |= CAN_ERR_FLAG;
+        }
+        if message.brs {
+            frame.flags |= CANFD_BRS;
+        }
+        if message.esi {
+            frame.flags |= CANFD_ESI;
+        }
+        frame.len = (message.length as usize).min(message.max_length()) as u8;
+        frame.data = message.data;
+        frame
+    }
+}
+
+// must be called right after the failing call, before errno is changed by another call
+fn error_from_errno(message: &'static str) -> CANError {
+    let errno = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+    let kind = match errno {
+        // the kernel reports these when the interface went down or was removed
+        ENETDOWN | ENODEV | ENXIO => ErrorKind::DeviceGone,
+        ENOBUFS => ErrorKind::QueueFull,
+        _ => ErrorKind::Other
+    };
+    CANError::with_code(kind, errno as u64, message)
+}
+
+impl Drop for SocketCANAdaptor {
+    fn drop(&mut self) {
+        unsafe { close(self.fd) };
+        // no return value, if it fails, it fails...
+    }
+}
+
+
+impl CANAdaptor for SocketCANAdaptor {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CANMessage>, CANError> {
+        let mut fds = pollfd { fd: self.fd, events: POLLIN, revents: 0 };
+        let status = unsafe { poll(&mut fds, 1, timeout.as_millis() as c_int) };
+        if status < 0 {
+            return Err(error_from_errno("poll error on CAN_RAW socket"));
+        }
+        if status == 0 {
+            return Ok(None);
+        }
+        let mut frame: canfd_frame = unsafe { mem::zeroed() };
+        let (count, timestamp) = self.read_frame(&mut frame)?;
+        let mut message = match count {
+            CAN_MTU => SocketCANAdaptor::message_from_frame(&frame, false),
+            CANFD_MTU => SocketCANAdaptor::message_from_frame(&frame, true),
+            _ => return Err(CANError::new(ErrorKind::Other, "incomplete CAN frame read from CAN_RAW socket"))
+        };
+        message.timestamp = timestamp.map(|t| self.host_time(t));
+        log(&format!("<< {}", &message));
+        Ok(Some(message))
+    }
+
+    fn send(&mut self, message: &CANMessage) -> Result<(), CANError> {
+        if message.fd && !self.fd_frames {
+            return Err(CANError::new(ErrorKind::Unsupported, "cannot send FD frame; FD frames are not enabled for this adaptor"));
+        }
+        let frame = SocketCANAdaptor::frame_from_message(message);
+        let mtu = if message.fd { CANFD_MTU } else { CAN_MTU };
+        let count = unsafe { write(self.fd, &frame as *const canfd_frame as *const c_void, mtu) };
+        if count < 0 {
+            return Err(error_from_errno("write error on CAN_RAW socket"));
+        }
+        if count as usize != mtu {
+            return Err(CANError::new(ErrorKind::Other, "incomplete CAN frame written to CAN_RAW socket"));
+        }
+        log(&format!(">> {}", &message));
+        Ok(())
+    }
+
+}
+
+
+fn log(message: &str) {
+    println!("{}", message);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_extended_remote_frame_to_message() {
+        let mut frame: canfd_frame = unsafe { mem::zeroed() };
+        frame.can_id = 0x18DAF110 | CAN_EFF_FLAG | CAN_RTR_FLAG;
+        frame.len = 2;
+        frame.data[0] = 0xCA;
+
+        let message = SocketCANAdaptor::message_from_frame(&frame, false);
+
+        assert_eq!(0x18DAF110, message.id);
+        assert_eq!(FrameFormat::Extended, message.format);
+        assert_eq!(true, message.rtr);
+        assert_eq!(2, message.length);
+        assert_eq!(0xCA, message.data[0]);
+    }
+
+    #[test]
+    fn converts_fd_frame_to_message() {
+        let mut frame: canfd_frame = unsafe { mem::zeroed() };
+        frame.can_id = 0x101;
+        frame.flags = CANFD_BRS;
+        frame.len = 12;
+        frame.data[11] = 0xCA;
+
+        let message = SocketCANAdaptor::message_from_frame(&frame, true);
+
+        assert_eq!(true, message.fd);
+        assert_eq!(true, message.brs);
+        assert_eq!(12, message.length);
+        assert_eq!(0xCA, message.data[11]);
+    }
+
+    #[test]
+    fn aligns_kernel_timestamps_with_host_clock() {
+        let mut adaptor = SocketCANAdaptor { fd: -1, fd_frames: false, clock_offset: None };
+
+        let first = adaptor.host_time(1_600_000_000_000_000);
+        let second = adaptor.host_time(1_600_000_000_002_500);
+
+        assert!(first <= utils::monotonic_micros());
+        assert_eq!(first + 2_500, second);
+    }
+
+    #[test]
+    fn converts_standard_message_to_frame() {
+        let message = CANMessage::with_content(0x101, &[0x20, 0x30]);
+
+        let frame = SocketCANAdaptor::frame_from_message(&message);
+
+        assert_eq!(0x101, frame.can_id);
+        assert_eq!(2, frame.len);
+        assert_eq!([0x20, 0x30, 0, 0, 0, 0, 0, 0], frame.data[..8]);
+    }
+
+    #[test]
+    fn converts_extended_message_to_frame() {
+        let mut message = CANMessage::with_content(0x18DAF110, &[]);
+        message.format = FrameFormat::Extended;
+
+        let frame = SocketCANAdaptor::frame_from_message(&message);
+
+        assert_eq!(0x18DAF110 | CAN_EFF_FLAG, frame.can_id);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use crate::can::{CANMessage, CANAdaptor, CANError, ErrorKind, AdaptorConfig};
+
+
+// All virtual adaptors attached to a channel with the same name form a bus. Like a CAN
+// controller, an adaptor receives the messages sent by all other adaptors on the bus but
+// not the ones it sends itself.
+
+type Bus = Vec<(usize, Sender<CANMessage>)>;
+
+static BUSES: OnceLock<Mutex<HashMap<String, Bus>>> = OnceLock::new();
+static NEXT_ADAPTOR_ID: AtomicUsize = AtomicUsize::new(0);
+
+fn buses() -> &'static Mutex<HashMap<String, Bus>> {
+    BUSES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+
+pub struct VirtualAdaptor {
+    id: usize,
+    channel: String,
+    receiver: Receiver<CANMessage>,
+}
+
+
+impl VirtualAdaptor {
+    pub fn create(config: &AdaptorConfig) -> Result<Box<dyn CANAdaptor>, CANError> {
+        Ok(Box::new(VirtualAdaptor::new(&config.option_or("channel", "0"))))
+    }
+
+    pub fn new(channel: &str) -> VirtualAdaptor {
+        let id = NEXT_ADAPTOR_ID.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = mpsc::channel();
+        let mut buses = buses().lock().unwrap();
+        buses.entry(channel.to_string()).or_default().push((id, sender));
+        log(&format!("Attached virtual adaptor to channel {}", channel));
+        VirtualAdaptor { id, channel: channel.to_string(), receiver }
+    }
+}
+
+impl Drop for VirtualAdaptor {
+    fn drop(&mut self) {
+        let mut buses = buses().lock().unwrap();
+        if let Some(bus) = buses.get_mut(&self.channel) {
+            bus.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+
+impl CANAdaptor for VirtualAdaptor {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CANMessage>, CANError> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(message) => {
+                log(&format!("<< {}", &message));
+                Ok(Some(message))
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(CANError::new(ErrorKind::DeviceGone, "virtual bus disconnected"))
+        }
+    }
+
+    fn send(&mut self, message: &CANMessage) -> Result<(), CANError> {
+        let buses = buses().lock().unwrap();
+        if let Some(bus) = buses.get(&self.channel) {
+            for (id, sender) in bus {
+                if *id != self.id {
+                    // the other adaptor may be just going away; that's not the sender's problem
+                    let _ = sender.send(*message);
+                }
+            }
+        }
+        log(&format!(">> {}", &message));
+        Ok(())
+    }
+
+}
+
+
+fn log(message: &str) {
+    println!("{}", message);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivers_message_to_other_adaptors_on_same_channel() {
+        let mut a1 = VirtualAdaptor::new("test-same");
+        let mut a2 = VirtualAdaptor::new("test-same");
+
+        a1.send(&CANMessage::with_content(0x101, &[0xCA])).unwrap();
+
+        assert_eq!(0x101, a2.receive(Duration::from_millis(10)).unwrap().unwrap().id);
+        assert!(a1.receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn does_not_deliver_message_to_adaptors_on_other_channels() {
+        let mut a1 = VirtualAdaptor::new("test-other-1");
+        let a2 = VirtualAdaptor::new("test-other-2");
+
+        a1.send(&CANMessage::with_content(0x101, &[0xCA])).unwrap();
+
+        assert!(a2.receiver.try_recv().is_err());
+    }
+}
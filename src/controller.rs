@@ -1,9 +1,16 @@
 use std::borrow::BorrowMut;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::thread::JoinHandle;
 
 use gotham_derive::*;
+use serde_derive::*;
 
+use crate::can::{AdaptorConfig, CANError, ErrorClass};
 use crate::imposter;
 use crate::imposter::Imposter;
 use crate::webapi;
@@ -11,6 +18,8 @@ use crate::webapi;
 #[derive(Clone, StateData)]
 pub struct ImposterList {
     inner: Arc<Mutex<Vec<Imposter>>>,
+    ports: Option<Arc<PortList>>,
+    dbc_dir: PathBuf,
 }
 
 
@@ -18,33 +27,68 @@ impl ImposterList {
     pub fn new() -> Self {
         Self {
             inner: Arc::new(Mutex::new(Vec::new())),
+            ports: None,
+            dbc_dir: PathBuf::from("."),
         }
     }
 
+    // a list created this way starts and stops a port, i.e. a thread with its own CAN adaptor
+    // running the imposter loop, whenever an imposter is added or removed
+    pub fn with_ports(default_adaptor: AdaptorConfig) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Vec::new())),
+            ports: Some(Arc::new(PortList::new(default_adaptor))),
+            dbc_dir: PathBuf::from("."),
+        }
+    }
+
+    // the directory that DBC files of imposters added to the list are read from
+    pub fn with_dbc_dir(mut self, dbc_dir: PathBuf) -> Self {
+        self.dbc_dir = dbc_dir;
+        self
+    }
+
+    pub fn dbc_dir(&self) -> &Path {
+        &self.dbc_dir
+    }
+
     pub fn upsert(&self, imposter: Imposter) -> bool {
         let mut did_insert = true;
-        let mut guard = self.inner.lock().unwrap();
-        let list = guard.borrow_mut();
-        for i in 0..(list.len()) {
-            if list[i].id == imposter.id {
-                list.remove(i);
+        let mut prev_adaptor = None;
+        let id = imposter.id;
+        let adaptor = imposter.adaptor.clone();
+        {
+            let mut guard = self.inner.lock().unwrap();
+            let list = guard.borrow_mut();
+            if let Some(i) = list.iter().position(|i| i.id == imposter.id) {
+                prev_adaptor = list.remove(i).adaptor;
                 did_insert = false;
             }
+            list.push(imposter);
+        }
+        if let Some(ports) = &self.ports {
+            if !did_insert && prev_adaptor != adaptor {
+                ports.stop(id);
+            }
+            ports.start_if_needed(id, adaptor, self.clone());
         }
-        list.push(imposter);
         did_insert
     }
 
     pub fn delete_by_id(&self, id: u32) -> bool {
-        let mut guard = self.inner.lock().unwrap();
-        let list = guard.borrow_mut();
-        for i in 0..(list.len()) {
-            if list[i].id == id {
+        let mut did_delete = false;
+        {
+            let mut guard = self.inner.lock().unwrap();
+            let list = guard.borrow_mut();
+            if let Some(i) = list.iter().position(|i| i.id == id) {
                 list.remove(i);
-                return true;
+                did_delete = true;
             }
         }
-        false
+        if let Some(ports) = &self.ports {
+            ports.stop(id);
+        }
+        did_delete
     }
 
     pub fn get_all(&self) -> Vec<Imposter> {
@@ -62,6 +106,19 @@ impl ImposterList {
         None
     }
 
+    // returns None when there is no imposter with the given id
+    pub fn update_by_id<F, R>(&self, id: u32, func: F) -> Option<R> where F: FnOnce(&mut Imposter) -> R {
+        let mut guard = self.inner.lock().unwrap();
+        guard.borrow_mut().iter_mut().find(|i| i.id == id).map(func)
+    }
+
+    // returns None when there is no imposter with the given id
+    pub fn port_status(&self, id: u32) -> Option<PortStatus> {
+        self.get_by_id(id)?;
+        let status = self.ports.as_ref().and_then(|ports| ports.status(id));
+        Some(status.unwrap_or_else(PortStatus::stopped))
+    }
+
     pub fn do_with_imposter_by_id<F>(&mut self, id: u32, mut func: F) where F: FnMut(&mut Imposter) {
         let mut guard = self.inner.lock().unwrap();
         for imposter in guard.borrow_mut().iter_mut() {
@@ -73,26 +130,120 @@ impl ImposterList {
 }
 
 
-pub fn run(imposter_files: Vec<String>) {
-    let list = ImposterList::new();
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum PortState {
+    #[serde(rename = "starting")]    Starting,
+    #[serde(rename = "running")]     Running,
+    // the adaptor failed and the port is waiting to initialize it again
+    #[serde(rename = "recovering")]  Recovering,
+    #[serde(rename = "stopped")]     Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortError {
+    pub class: ErrorClass,
+    #[serde(flatten)]
+    pub error: CANError,
+}
+
+// the health of the adaptor of a port, as reported by the imposter loop
+#[derive(Debug, Clone, Serialize)]
+pub struct PortStatus {
+    pub state: PortState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adaptor: Option<String>,
+    #[serde(rename = "errorCount")]
+    pub error_count: u64,
+    pub restarts: u64,
+    #[serde(rename = "lastError", skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<PortError>,
+}
+
+impl PortStatus {
+    pub fn new(adaptor: &str) -> PortStatus {
+        PortStatus { state: PortState::Starting, adaptor: Some(adaptor.to_string()), error_count: 0, restarts: 0, last_error: None }
+    }
+
+    pub fn stopped() -> PortStatus {
+        PortStatus { state: PortState::Stopped, adaptor: None, error_count: 0, restarts: 0, last_error: None }
+    }
+
+    pub fn record_error(&mut self, error: &CANError) {
+        self.error_count += 1;
+        self.last_error = Some(PortError { class: error.class(), error: error.clone() });
+    }
+}
+
+
+struct Port {
+    stop: Arc<AtomicBool>,
+    status: Arc<Mutex<PortStatus>>,
+    handle: JoinHandle<()>,
+}
+
+struct PortList {
+    default_adaptor: AdaptorConfig,
+    ports: Mutex<HashMap<u32, Port>>,
+}
+
+impl PortList {
+    fn new(default_adaptor: AdaptorConfig) -> Self {
+        Self {
+            default_adaptor,
+            ports: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn start_if_needed(&self, id: u32, adaptor: Option<AdaptorConfig>, list: ImposterList) {
+        let mut ports = self.ports.lock().unwrap();
+        if ports.contains_key(&id) {
+            return;
+        }
+        let config = adaptor.unwrap_or_else(|| self.default_adaptor.clone());
+        let stop = Arc::new(AtomicBool::new(false));
+        let status = Arc::new(Mutex::new(PortStatus::new(&config.adaptor_type)));
+        let cloned_stop = stop.clone();
+        let cloned_status = status.clone();
+        println!("Starting port for imposter {} with {} adaptor", id, config.adaptor_type);
+        let handle = thread::spawn(move || {
+            imposter::run(id, list, &config, &cloned_stop, &cloned_status)
+        });
+        ports.insert(id, Port { stop, status, handle });
+    }
+
+    fn status(&self, id: u32) -> Option<PortStatus> {
+        let ports = self.ports.lock().unwrap();
+        ports.get(&id).map(|port| port.status.lock().unwrap().clone())
+    }
+
+    fn stop(&self, id: u32) {
+        let port = self.ports.lock().unwrap().remove(&id);
+        if let Some(port) = port {
+            println!("Stopping port for imposter {}", id);
+            port.stop.store(true, Ordering::SeqCst);
+            if port.handle.join().is_err() {
+                println!("Port for imposter {} terminated abnormally", id);
+            }
+        }
+    }
+}
+
+
+pub fn run(imposter_files: Vec<String>, default_adaptor: AdaptorConfig, dbc_dir: PathBuf) {
+    let list = ImposterList::with_ports(default_adaptor).with_dbc_dir(dbc_dir);
 
     for file in imposter_files {
-        let imposter = Imposter::from_file(&file);
-        list.upsert(imposter);
+        match Imposter::from_file(&file, list.dbc_dir()) {
+            Ok(imposter) => { list.upsert(imposter); }
+            Err(error) => {
+                println!("Failed to load imposter from file {}: {}", file, error);
+                process::exit(1);
+            }
+        }
     }
 
-    let cloned_list = list.clone();
     let addr = format!("{}:{}", "localhost", 8080);
-    thread::spawn(move || {
-        webapi::run(addr, cloned_list);
-    });
-
-    let cloned_list = list.clone();
-    let port_id = 0;
-    let h = thread::spawn(move || {
-        imposter::run(port_id, cloned_list)
-    });
-    h.join().unwrap();
+    webapi::run(addr, list);
 }
 
 
@@ -105,8 +256,8 @@ mod tests {
     fn upsert_differentiates_imposters_with_different_ids() {
         let list = ImposterList::new();
 
-        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#));
-        list.upsert(Imposter::from_json(r#"{"id": 2, "stubs": []}"#));
+        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#).unwrap());
+        list.upsert(Imposter::from_json(r#"{"id": 2, "stubs": []}"#).unwrap());
 
         assert_eq!(2, list.get_all().len());
     }
@@ -115,12 +266,51 @@ mod tests {
     fn upsert_replaces_existing_imposter_with_same_id() {
         let list = ImposterList::new();
 
-        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#));
-        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#));
+        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#).unwrap());
+        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#).unwrap());
 
         assert_eq!(1, list.get_all().len());
     }
 
+    #[test]
+    fn starts_and_stops_port_for_imposter() {
+        let list = ImposterList::with_ports(AdaptorConfig::new("virtual"));
+        let ports = list.ports.clone().unwrap();
+
+        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#).unwrap());
+        assert!(ports.ports.lock().unwrap().contains_key(&1));
+
+        list.delete_by_id(1);
+        assert!(!ports.ports.lock().unwrap().contains_key(&1));
+    }
+
+    #[test]
+    fn restarts_port_when_adaptor_changes() {
+        let list = ImposterList::with_ports(AdaptorConfig::new("virtual"));
+        let ports = list.ports.clone().unwrap();
+
+        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#).unwrap());
+        let first_thread = ports.ports.lock().unwrap()[&1].handle.thread().id();
+        list.upsert(Imposter::from_json(r#"{"id": 1, "adaptor": { "type": "virtual", "channel": "1" }, "stubs": []}"#).unwrap());
+        let second_thread = ports.ports.lock().unwrap()[&1].handle.thread().id();
+
+        assert_ne!(first_thread, second_thread);
+        list.delete_by_id(1);
+    }
+
+    #[test]
+    fn reports_status_of_port() {
+        let list = ImposterList::with_ports(AdaptorConfig::new("virtual"));
+
+        assert!(list.port_status(1).is_none());
+        list.upsert(Imposter::from_json(r#"{"id": 1, "stubs": []}"#).unwrap());
+        let status = list.port_status(1).unwrap();
+
+        assert_eq!(Some("virtual".to_string()), status.adaptor);
+        assert_ne!(PortState::Stopped, status.state);
+        list.delete_by_id(1);
+    }
+
 }
 
 
@@ -0,0 +1,403 @@
+use std::fs;
+use std::path::{Component, Path};
+
+use crate::can::{CANMessage, FrameFormat};
+use crate::literal::MAX_EXTENDED_ID;
+
+
+// bit 31 of a message id in a DBC file marks messages with an extended id
+const EXTENDED_ID_FLAG: u32 = 0x8000_0000;
+
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ByteOrder {
+    // the start bit is the least significant bit
+    LittleEndian,
+    // the start bit is the most significant bit, counted in the sawtooth order of DBC files
+    BigEndian,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Multiplex {
+    None,
+    // the signal that selects which of the multiplexed signals are present
+    Multiplexor,
+    // the signal is only present when the multiplexor has this value
+    Multiplexed(u64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub start: usize,
+    pub size: usize,
+    pub byte_order: ByteOrder,
+    pub signed: bool,
+    pub factor: f64,
+    pub offset: f64,
+    pub min: f64,
+    pub max: f64,
+    pub multiplex: Multiplex,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageDefinition {
+    pub id: u32,
+    pub extended: bool,
+    pub name: String,
+    pub length: usize,
+    pub signals: Vec<Signal>,
+}
+
+// The messages and signals described in a DBC file. Only message and signal definitions are
+// read; all other sections of the file are ignored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dbc {
+    pub messages: Vec<MessageDefinition>,
+}
+
+
+impl Dbc {
+    // DBC files can only be read from the given directory and its subdirectories, because the
+    // file name comes from imposter definitions posted to the web API
+    pub fn from_file(dir: &Path, filename: &str) -> Result<Dbc, String> {
+        if !Path::new(filename).components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("DBC file {} must be a relative path without ..", filename));
+        }
+        // symbolic links could still point outside of the directory
+        let read_error = |e| format!("cannot read DBC file {}: {}", filename, e);
+        let dir = dir.canonicalize().map_err(read_error)?;
+        let path = dir.join(filename).canonicalize().map_err(read_error)?;
+        if !path.starts_with(&dir) {
+            return Err(format!("DBC file {} is not in the DBC directory", filename));
+        }
+        let contents = fs::read_to_string(path).map_err(read_error)?;
+        Dbc::parse(&contents).map_err(|e| format!("cannot parse DBC file {}: {}", filename, e))
+    }
+
+    pub fn parse(contents: &str) -> Result<Dbc, String> {
+        let mut messages: Vec<MessageDefinition> = Vec::new();
+        let mut in_message = false;
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if let Some(definition) = line.strip_prefix("BO_ ") {
+                let message = parse_message(definition).map_err(|e| format!("line {}: {}", n + 1, e))?;
+                messages.push(message);
+                in_message = true;
+            } else if let Some(definition) = line.strip_prefix("SG_ ") {
+                let signal = parse_signal(definition).map_err(|e| format!("line {}: {}", n + 1, e))?;
+                match messages.last_mut() {
+                    Some(message) if in_message => message.signals.push(signal),
+                    _ => return Err(format!("line {}: signal {} is not part of a message", n + 1, signal.name))
+                }
+            } else if !line.is_empty() {
+                in_message = false;
+            }
+        }
+        Ok(Dbc { messages })
+    }
+
+    pub fn message(&self, name: &str) -> Option<&MessageDefinition> {
+        self.messages.iter().find(|m| m.name == name)
+    }
+
+    pub fn find_signal(&self, message: &str, signal: &str) -> Result<(&MessageDefinition, &Signal), String> {
+        let definition = self.message(message).ok_or_else(|| format!("DBC file has no message {}", message))?;
+        let signal = definition.signal(signal).ok_or_else(|| format!("message {} has no signal {}", message, signal))?;
+        Ok((definition, signal))
+    }
+}
+
+impl MessageDefinition {
+    pub fn signal(&self, name: &str) -> Option<&Signal> {
+        self.signals.iter().find(|s| s.name == name)
+    }
+
+    pub fn matches_id(&self, message: &CANMessage) -> bool {
+        message.id == self.id && (message.format == FrameFormat::Extended) == self.extended
+    }
+
+    pub fn multiplexor(&self) -> Option<&Signal> {
+        self.signals.iter().find(|s| s.multiplex == Multiplex::Multiplexor)
+    }
+
+    // Returns the data of a message with the given physical values; signals that are not given
+    // are zero. The multiplexor is set to select the multiplexed signals. The values must have
+    // been checked with Signal::to_raw.
+    pub fn encode(&self, values: &[(&Signal, f64)]) -> Vec<u8> {
+        let mut data = vec![0u8; self.length];
+        for (signal, value) in values {
+            if let (Multiplex::Multiplexed(m), Some(multiplexor)) = (signal.multiplex, self.multiplexor()) {
+                multiplexor.set_raw_value(&mut data, m);
+            }
+            signal.set_raw_value(&mut data, signal.to_raw(*value).unwrap_or(0));
+        }
+        data
+    }
+
+    // returns the physical value of the signal, or None when the signal is not present in the data
+    pub fn decode(&self, signal: &Signal, data: &[u8]) -> Option<f64> {
+        if let Multiplex::Multiplexed(value) = signal.multiplex {
+            if self.multiplexor()?.raw_value(data)? != value {
+                return None;
+            }
+        }
+        signal.decode(data)
+    }
+}
+
+impl Signal {
+    pub fn decode(&self, data: &[u8]) -> Option<f64> {
+        let raw = self.raw_value(data)?;
+        let value = if self.signed && self.size < 64 && raw & (1 << (self.size - 1)) != 0 {
+            (raw | (u64::MAX << self.size)) as i64 as f64
+        } else if self.signed {
+            raw as i64 as f64
+        } else {
+            raw as f64
+        };
+        Some(value * self.factor + self.offset)
+    }
+
+    // converts a physical value to the raw value of the signal, which has only the bits of the signal
+    pub fn to_raw(&self, value: f64) -> Result<u64, String> {
+        let raw = ((value - self.offset) / self.factor).round();
+        let (min, max) = if self.signed {
+            (-(2f64.powi(self.size as i32 - 1)), 2f64.powi(self.size as i32 - 1) - 1.0)
+        } else {
+            (0.0, 2f64.powi(self.size as i32) - 1.0)
+        };
+        if !(min..=max).contains(&raw) {
+            return Err(format!("value {} is out of range for signal {}", value, self.name));
+        }
+        let mask = if self.size < 64 { (1u64 << self.size) - 1 } else { u64::MAX };
+        Ok((raw as i64 as u64) & mask)
+    }
+
+    // bits of the signal that do not fit into the data are ignored
+    pub fn set_raw_value(&self, data: &mut [u8], raw: u64) {
+        for (i, pos) in self.bit_positions().into_iter().enumerate() {
+            let bit = match self.byte_order {
+                ByteOrder::LittleEndian => (raw >> i) & 1,
+                ByteOrder::BigEndian => (raw >> (self.size - 1 - i)) & 1,
+            };
+            if let Some(byte) = data.get_mut(pos / 8) {
+                *byte = (*byte & !(1 << (pos % 8))) | ((bit as u8) << (pos % 8));
+            }
+        }
+    }
+
+    // returns None when the data is too short to contain the signal
+    pub fn raw_value(&self, data: &[u8]) -> Option<u64> {
+        let mut raw = 0u64;
+        for (i, pos) in self.bit_positions().into_iter().enumerate() {
+            let bit = (data.get(pos / 8)? >> (pos % 8)) & 1;
+            match self.byte_order {
+                ByteOrder::LittleEndian => raw |= (bit as u64) << i,
+                ByteOrder::BigEndian => raw = (raw << 1) | bit as u64,
+            }
+        }
+        Some(raw)
+    }
+
+    // positions of the bits of the signal in the data, for little endian signals starting with
+    // the least significant bit, for big endian signals starting with the most significant bit
+    fn bit_positions(&self) -> Vec<usize> {
+        let mut positions = Vec::with_capacity(self.size);
+        let mut pos = self.start;
+        for _ in 0..self.size {
+            positions.push(pos);
+            pos = match self.byte_order {
+                ByteOrder::LittleEndian => pos + 1,
+                ByteOrder::BigEndian if pos.is_multiple_of(8) => pos + 15,
+                ByteOrder::BigEndian => pos - 1,
+            };
+        }
+        positions
+    }
+}
+
+
+// e.g. 2364540158 EEC1: 8 Vector__XXX
+fn parse_message(definition: &str) -> Result<MessageDefinition, String> {
+    let error = || "invalid message definition".to_string();
+    let (head, tail) = definition.split_once(':').ok_or_else(error)?;
+    let mut head = head.split_whitespace();
+    let raw_id: u32 = head.next().and_then(|s| s.parse().ok()).ok_or_else(error)?;
+    let name = head.next().ok_or_else(error)?.to_string();
+    let length: usize = tail.split_whitespace().next().and_then(|s| s.parse().ok()).ok_or_else(error)?;
+    let extended = raw_id & EXTENDED_ID_FLAG != 0;
+    let id = raw_id & MAX_EXTENDED_ID as u32;
+    Ok(MessageDefinition { id, extended, name, length, signals: Vec::new() })
+}
+
+// e.g. EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] "rpm" Vector__XXX
+fn parse_signal(definition: &str) -> Result<Signal, String> {
+    let error = || "invalid signal definition".to_string();
+    let (head, tail) = definition.split_once(':').ok_or_else(error)?;
+    let mut head = head.split_whitespace();
+    let name = head.next().ok_or_else(error)?.to_string();
+    let multiplex = match head.next() {
+        None => Multiplex::None,
+        Some("M") => Multiplex::Multiplexor,
+        Some(m) if m.starts_with('m') => Multiplex::Multiplexed(m[1..].parse().map_err(|_| error())?),
+        Some(_) => return Err(error())
+    };
+
+    let mut tail = tail.split_whitespace();
+    let layout = tail.next().ok_or_else(error)?;
+    let (start, layout) = layout.split_once('|').ok_or_else(error)?;
+    let (size, flags) = layout.split_once('@').ok_or_else(error)?;
+    let start: usize = start.parse().map_err(|_| error())?;
+    let size: usize = size.parse().map_err(|_| error())?;
+    let byte_order = match flags.get(0..1) {
+        Some("1") => ByteOrder::LittleEndian,
+        Some("0") => ByteOrder::BigEndian,
+        _ => return Err(error())
+    };
+    let signed = match flags.get(1..2) {
+        Some("-") => true,
+        Some("+") => false,
+        _ => return Err(error())
+    };
+    if size == 0 || size > 64 || start >= 512 {
+        return Err(error());
+    }
+
+    let (factor, offset) = parse_pair(tail.next(), '(', ',', ')').ok_or_else(error)?;
+    let (min, max) = parse_pair(tail.next(), '[', '|', ']').ok_or_else(error)?;
+
+    Ok(Signal { name, start, size, byte_order, signed, factor, offset, min, max, multiplex })
+}
+
+fn parse_pair(text: Option<&str>, open: char, separator: char, close: char) -> Option<(f64, f64)> {
+    let text = text?.strip_prefix(open)?.strip_suffix(close)?;
+    let (a, b) = text.split_once(separator)?;
+    Some((a.parse().ok()?, b.parse().ok()?))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DBC: &str = r#"
+VERSION ""
+
+BU_: ECU Tester
+
+BO_ 2364540158 EEC1: 8 ECU
+ SG_ EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] "rpm" Tester
+ SG_ Torque : 16|8@1- (1,-10) [-138|117] "%" Tester
+
+BO_ 1024 Status: 4 ECU
+ SG_ Temperature : 7|12@0+ (0.1,-40) [-40|369.5] "degC" Tester
+ SG_ Page M : 31|4@0+ (1,0) [0|15] "" Tester
+ SG_ PageOneValue m1 : 27|4@0+ (1,0) [0|15] "" Tester
+
+CM_ SG_ 1024 Temperature "Coolant temperature";
+"#;
+
+    #[test]
+    fn parses_messages_and_signals() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        assert_eq!(2, dbc.messages.len());
+        let message = dbc.message("EEC1").unwrap();
+        assert_eq!(0x0CF0_04FE, message.id);
+        assert_eq!(true, message.extended);
+        assert_eq!(8, message.length);
+        let signal = message.signal("EngineSpeed").unwrap();
+        assert_eq!(24, signal.start);
+        assert_eq!(16, signal.size);
+        assert_eq!(ByteOrder::LittleEndian, signal.byte_order);
+        assert_eq!(0.125, signal.factor);
+        let message = dbc.message("Status").unwrap();
+        assert_eq!(false, message.extended);
+        assert_eq!(Multiplex::Multiplexed(1), message.signal("PageOneValue").unwrap().multiplex);
+    }
+
+    #[test]
+    fn fails_with_line_number_when_signal_is_invalid() {
+        let error = Dbc::parse("BO_ 100 Test: 8 ECU\n SG_ Broken : 8|x@1+ (1,0) [0|1] \"\" ECU\n").unwrap_err();
+        assert_eq!("line 2: invalid signal definition", error);
+    }
+
+    #[test]
+    fn only_reads_files_below_directory() {
+        let dir = Path::new("tests");
+        assert!(Dbc::from_file(dir, "it_imposter.dbc").is_ok());
+        assert!(Dbc::from_file(dir, "../tests/it_imposter.dbc").unwrap_err().contains("relative path"));
+        assert!(Dbc::from_file(dir, "/etc/passwd").unwrap_err().contains("relative path"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_symbolic_links_out_of_directory() {
+        let dir = std::env::temp_dir().join(format!("candouble-dbc-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let target = Path::new("tests/it_imposter.dbc").canonicalize().unwrap();
+        let _ = fs::remove_file(dir.join("link.dbc"));
+        std::os::unix::fs::symlink(target, dir.join("link.dbc")).unwrap();
+
+        let result = Dbc::from_file(&dir, "link.dbc");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(result.unwrap_err().contains("not in the DBC directory"));
+    }
+
+    #[test]
+    fn decodes_little_endian_signals_with_scaling_and_sign() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let message = dbc.message("EEC1").unwrap();
+        let data = [0x00, 0x00, 0xFE, 0x40, 0x1F, 0x00, 0x00, 0x00];
+        assert_eq!(Some(1000.0), message.decode(message.signal("EngineSpeed").unwrap(), &data));
+        assert_eq!(Some(-12.0), message.decode(message.signal("Torque").unwrap(), &data));
+    }
+
+    #[test]
+    fn decodes_big_endian_signals() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let message = dbc.message("Status").unwrap();
+        // raw temperature is 0x2BC, which is 700, in the first 12 bits
+        let data = [0x2B, 0xC0, 0x00, 0x15];
+        assert_eq!(Some(30.0), message.decode(message.signal("Temperature").unwrap(), &data).map(|v| v.round()));
+        assert_eq!(Some(1.0), message.decode(message.signal("Page").unwrap(), &data));
+        assert_eq!(Some(5.0), message.decode(message.signal("PageOneValue").unwrap(), &data));
+    }
+
+    #[test]
+    fn does_not_decode_signal_that_is_not_present() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let message = dbc.message("Status").unwrap();
+        assert_eq!(None, message.decode(message.signal("PageOneValue").unwrap(), &[0x2B, 0xC0, 0x00, 0x25]));
+        assert_eq!(None, message.decode(message.signal("Page").unwrap(), &[0x2B, 0xC0]));
+    }
+
+    #[test]
+    fn encodes_signals_with_scaling_and_byte_order() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let message = dbc.message("EEC1").unwrap();
+        let data = message.encode(&[(message.signal("EngineSpeed").unwrap(), 1000.0), (message.signal("Torque").unwrap(), -12.0)]);
+        assert_eq!(vec![0x00, 0x00, 0xFE, 0x40, 0x1F, 0x00, 0x00, 0x00], data);
+        let message = dbc.message("Status").unwrap();
+        let data = message.encode(&[(message.signal("Temperature").unwrap(), 30.0)]);
+        assert_eq!(vec![0x2B, 0xC0, 0x00, 0x00], data);
+    }
+
+    #[test]
+    fn sets_multiplexor_when_encoding_multiplexed_signal() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let message = dbc.message("Status").unwrap();
+        let data = message.encode(&[(message.signal("PageOneValue").unwrap(), 5.0)]);
+        assert_eq!(vec![0x00, 0x00, 0x00, 0x15], data);
+    }
+
+    #[test]
+    fn rejects_values_that_do_not_fit_signal() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let message = dbc.message("EEC1").unwrap();
+        assert!(message.signal("EngineSpeed").unwrap().to_raw(-1.0).is_err());
+        assert!(message.signal("EngineSpeed").unwrap().to_raw(8192.0).is_err());
+        assert_eq!(Ok(0xF6), message.signal("Torque").unwrap().to_raw(-20.0));
+    }
+}
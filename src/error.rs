@@ -0,0 +1,113 @@
+use std::{error, fmt, io};
+
+
+// A problem found when validating a definition. The path points to the element that is invalid,
+// relative to the definition that was validated, e.g. stubs[2].responses[0].data[9]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub path: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    pub fn new(path: &str, message: String) -> ValidationError {
+        ValidationError { path: path.to_string(), message }
+    }
+
+    // makes the path relative to the element that contains the invalid element
+    pub fn within(self, parent: &str) -> ValidationError {
+        ValidationError { path: join_path(parent, &self.path), message: self.message }
+    }
+}
+
+fn join_path(parent: &str, path: &str) -> String {
+    if path.is_empty() {
+        parent.to_string()
+    } else if path.starts_with('[') {
+        format!("{}{}", parent, path)
+    } else {
+        format!("{}.{}", parent, path)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ImposterError {
+    Io(io::Error),
+    // the document is not valid JSON
+    Syntax(serde_json::Error),
+    // the document is valid JSON but does not match what is expected at the path
+    Schema { path: String, error: serde_json::Error },
+    Invalid(ValidationError),
+}
+
+impl ImposterError {
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            ImposterError::Schema { path, .. } if !path.is_empty() => Some(path),
+            ImposterError::Invalid(e) if !e.path.is_empty() => Some(&e.path),
+            _ => None
+        }
+    }
+
+    // for documents that are parsed on their own but are part of a larger document
+    pub fn within(self, parent: &str) -> ImposterError {
+        match self {
+            ImposterError::Schema { path, error } => ImposterError::Schema { path: join_path(parent, &path), error },
+            ImposterError::Invalid(e) => ImposterError::Invalid(e.within(parent)),
+            other => other
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ImposterError::Io(e) => e.to_string(),
+            ImposterError::Syntax(e) => e.to_string(),
+            ImposterError::Schema { error, .. } => error.to_string(),
+            ImposterError::Invalid(e) => e.message.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ImposterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self, self.path()) {
+            (ImposterError::Io(e), _) => write!(f, "Error reading imposter definition: {}", e),
+            (ImposterError::Syntax(e), _) => write!(f, "Error parsing JSON document: {}", e),
+            (_, Some(path)) => write!(f, "Invalid definition at {}: {}", path, self.message()),
+            (_, None) => write!(f, "Invalid definition: {}", self.message()),
+        }
+    }
+}
+
+impl error::Error for ImposterError {}
+
+impl From<io::Error> for ImposterError {
+    fn from(error: io::Error) -> ImposterError {
+        ImposterError::Io(error)
+    }
+}
+
+impl From<ValidationError> for ImposterError {
+    fn from(error: ValidationError) -> ImposterError {
+        ImposterError::Invalid(error)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_paths_of_nested_elements() {
+        let e = ValidationError::new("data[9]", "too long".to_string()).within("responses[0]").within("stubs[2]");
+        assert_eq!("stubs[2].responses[0].data[9]", e.path);
+    }
+
+    #[test]
+    fn uses_parent_path_when_element_itself_is_invalid() {
+        let e = ValidationError::new("", "no responses".to_string()).within("stubs[2]");
+        assert_eq!("stubs[2]", e.path);
+    }
+}
@@ -0,0 +1,350 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
+
+use crate::can::CANMessage;
+use crate::error::ValidationError;
+use crate::literal::{self, Number};
+
+
+// An expression that computes a value from the incoming message, e.g. ${req.data[1] & 0x0F}. It
+// supports numbers, the id, length and data bytes of the request, arithmetic and bitwise
+// operators, and parentheses. Arithmetic wraps around, and division by zero results in zero.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(u64),
+    RequestId,
+    RequestLength,
+    RequestData(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Operator {
+    Add, Subtract, Multiply, Divide, Remainder,
+    And, Or, Xor, ShiftLeft, ShiftRight,
+}
+
+impl Operator {
+    // operators with higher precedence bind more tightly, as in C
+    fn precedence(self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::Xor => 2,
+            Operator::And => 3,
+            Operator::ShiftLeft | Operator::ShiftRight => 4,
+            Operator::Add | Operator::Subtract => 5,
+            Operator::Multiply | Operator::Divide | Operator::Remainder => 6,
+        }
+    }
+
+    fn apply(self, a: u64, b: u64) -> u64 {
+        match self {
+            Operator::Add => a.wrapping_add(b),
+            Operator::Subtract => a.wrapping_sub(b),
+            Operator::Multiply => a.wrapping_mul(b),
+            Operator::Divide => a.checked_div(b).unwrap_or(0),
+            Operator::Remainder => a.checked_rem(b).unwrap_or(0),
+            Operator::And => a & b,
+            Operator::Or => a | b,
+            Operator::Xor => a ^ b,
+            Operator::ShiftLeft => a.checked_shl(b as u32).unwrap_or(0),
+            Operator::ShiftRight => a.checked_shr(b as u32).unwrap_or(0),
+        }
+    }
+}
+
+// limits the depth of the expression tree, which is parsed and evaluated recursively
+const MAX_DEPTH: usize = 64;
+
+impl Expression {
+    // parses the text between ${ and }
+    pub fn parse(text: &str) -> Result<Expression, String> {
+        let tokens = tokenize(text)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let (expression, _) = parser.expression(0, 0)?;
+        if parser.pos < parser.tokens.len() {
+            return Err(format!("unexpected {:?} in expression", parser.tokens[parser.pos]));
+        }
+        Ok(expression)
+    }
+
+    // data bytes beyond the length of the request are zero
+    pub fn eval(&self, request: &CANMessage) -> u64 {
+        match self {
+            Expression::Number(n) => *n,
+            Expression::RequestId => request.id as u64,
+            Expression::RequestLength => request.length as u64,
+            Expression::RequestData(index) => {
+                let index = index.eval(request);
+                request.payload().get(index as usize).map_or(0, |b| *b as u64)
+            }
+            Expression::Not(e) => !e.eval(request),
+            Expression::Binary(op, a, b) => op.apply(a.eval(request), b.eval(request)),
+        }
+    }
+
+    pub fn uses_request(&self) -> bool {
+        match self {
+            Expression::Number(_) => false,
+            Expression::RequestId | Expression::RequestLength | Expression::RequestData(_) => true,
+            Expression::Not(e) => e.uses_request(),
+            Expression::Binary(_, a, b) => a.uses_request() || b.uses_request(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Operator(Operator),
+    Tilde,
+    Open(char),
+    Close(char),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        let token = match c {
+            ' ' => { i += 1; continue; }
+            '0'..='9' => {
+                let start = i;
+                while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                tokens.push(Token::Number(literal::parse_number(&literal)?));
+                continue;
+            }
+            'a'..='z' | 'A'..='Z' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+                continue;
+            }
+            '<' if next == Some('<') => { i += 1; Token::Operator(Operator::ShiftLeft) }
+            '>' if next == Some('>') => { i += 1; Token::Operator(Operator::ShiftRight) }
+            '+' => Token::Operator(Operator::Add),
+            '-' => Token::Operator(Operator::Subtract),
+            '*' => Token::Operator(Operator::Multiply),
+            '/' => Token::Operator(Operator::Divide),
+            '%' => Token::Operator(Operator::Remainder),
+            '&' => Token::Operator(Operator::And),
+            '|' => Token::Operator(Operator::Or),
+            '^' => Token::Operator(Operator::Xor),
+            '~' => Token::Tilde,
+            '(' | '[' => Token::Open(c),
+            ')' | ']' => Token::Close(c),
+            _ => return Err(format!("unexpected character {:?} in expression", c))
+        };
+        tokens.push(token);
+        i += 1;
+    }
+    Ok(tokens)
+}
+
+fn check_depth(depth: usize) -> Result<usize, String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("expression is nested too deeply; at most {} levels are allowed", MAX_DEPTH));
+    }
+    Ok(depth)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+// The parse functions take the depth at which the expression they parse is nested and return
+// the expression with the depth of its deepest node.
+impl Parser {
+    // precedence climbing; parses operators that bind more tightly than min_precedence
+    fn expression(&mut self, min_precedence: u8, depth: usize) -> Result<(Expression, usize), String> {
+        let (mut left, mut left_depth) = self.operand(depth)?;
+        while let Some(Token::Operator(op)) = self.tokens.get(self.pos).cloned() {
+            if op.precedence() <= min_precedence {
+                break;
+            }
+            self.pos += 1;
+            // the left operand moves down one level in the tree
+            let (right, right_depth) = self.expression(op.precedence(), depth + 1)?;
+            left_depth = check_depth((left_depth + 1).max(right_depth))?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok((left, left_depth))
+    }
+
+    fn operand(&mut self, depth: usize) -> Result<(Expression, usize), String> {
+        check_depth(depth)?;
+        let token = self.tokens.get(self.pos).cloned().ok_or("unexpected end of expression")?;
+        self.pos += 1;
+        match token {
+            Token::Number(n) => Ok((Expression::Number(n), depth)),
+            Token::Tilde => {
+                let (e, d) = self.operand(depth + 1)?;
+                Ok((Expression::Not(Box::new(e)), d))
+            }
+            // parentheses count as a level because they are parsed recursively
+            Token::Open('(') => {
+                let e = self.expression(0, depth + 1)?;
+                self.expect(')')?;
+                Ok(e)
+            }
+            Token::Name(ref name) if name == "req.id" => Ok((Expression::RequestId, depth)),
+            Token::Name(ref name) if name == "req.length" => Ok((Expression::RequestLength, depth)),
+            Token::Name(ref name) if name == "req.data" => {
+                self.expect('[')?;
+                let (index, d) = self.expression(0, depth + 1)?;
+                self.expect(']')?;
+                Ok((Expression::RequestData(Box::new(index)), d))
+            }
+            Token::Name(name) => Err(format!("unknown name {} in expression; expected req.id, req.length, or req.data[n]", name)),
+            t => Err(format!("unexpected {:?} in expression", t))
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Open(t)) | Some(Token::Close(t)) if *t == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(format!("expected {:?} in expression", c))
+        }
+    }
+}
+
+
+// An id or data byte of a response, which is either a number or an expression written as ${...}
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueTemplate {
+    Number(Number),
+    Expression { expression: Expression, text: String },
+}
+
+impl ValueTemplate {
+    pub fn value(&self, request: &CANMessage) -> u64 {
+        match self {
+            ValueTemplate::Number(n) => n.value,
+            ValueTemplate::Expression { expression, .. } => expression.eval(request),
+        }
+    }
+
+    pub fn uses_request(&self) -> bool {
+        match self {
+            ValueTemplate::Number(_) => false,
+            ValueTemplate::Expression { expression, .. } => expression.uses_request(),
+        }
+    }
+
+    // the value of an expression is only known when the response is generated
+    pub fn check_max(&self, path: &str, max: u64, what: &str) -> Result<(), ValidationError> {
+        match self {
+            ValueTemplate::Number(n) => n.check_max(path, max, what),
+            ValueTemplate::Expression { .. } => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for ValueTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueTemplate::Number(n) => write!(f, "{}", n),
+            ValueTemplate::Expression { text, .. } => write!(f, "{}", text),
+        }
+    }
+}
+
+impl Serialize for ValueTemplate {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueTemplate {
+    fn deserialize<D>(deserializer: D) -> Result<ValueTemplate, D::Error> where D: Deserializer<'de> {
+        let text = String::deserialize(deserializer)?;
+        match text.strip_prefix("${").and_then(|t| t.strip_suffix('}')) {
+            Some(inner) => {
+                let expression = Expression::parse(inner).map_err(D::Error::custom)?;
+                Ok(ValueTemplate::Expression { expression, text })
+            }
+            None => text.parse::<Number>().map(ValueTemplate::Number).map_err(D::Error::custom)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::from_json;
+
+    use super::*;
+
+    fn eval(text: &str, request: &CANMessage) -> u64 {
+        Expression::parse(text).unwrap().eval(request)
+    }
+
+    #[test]
+    fn evaluates_fields_of_request() {
+        let request = CANMessage::with_content(0x7E0, &[0x02, 0x10, 0x03]);
+        assert_eq!(0x7E8, eval("req.id + 8", &request));
+        assert_eq!(3, eval("req.length", &request));
+        assert_eq!(0x10, eval("req.data[1]", &request));
+        assert_eq!(0x03, eval("req.data[req.data[0]]", &request));
+        assert_eq!(0, eval("req.data[7]", &request));
+    }
+
+    #[test]
+    fn applies_operators_with_precedence_of_c() {
+        let request = CANMessage::new();
+        assert_eq!(14, eval("2 + 3 * 4", &request));
+        assert_eq!(20, eval("(2 + 3) * 4", &request));
+        assert_eq!(0x12, eval("0x10 | 0x03 & 0x02", &request));
+        assert_eq!(0xF0, eval("0x0F << 4 & 0xFF", &request));
+        assert_eq!(0xFE, eval("~1 & 0xFF", &request));
+        assert_eq!(0, eval("5 / 0", &request));
+    }
+
+    #[test]
+    fn fails_to_parse_invalid_expressions() {
+        assert!(Expression::parse("req.id +").is_err());
+        assert!(Expression::parse("req.data[1").is_err());
+        assert!(Expression::parse("request.id").is_err());
+        assert!(Expression::parse("1 $ 2").is_err());
+        assert!(Expression::parse("(1 + 2))").is_err());
+    }
+
+    #[test]
+    fn rejects_expressions_that_are_nested_too_deeply() {
+        assert!(Expression::parse(&"~".repeat(100_000)).unwrap_err().contains("nested too deeply"));
+        assert!(Expression::parse(&"(".repeat(100_000)).unwrap_err().contains("nested too deeply"));
+        assert!(Expression::parse(&"1 + ".repeat(100_000)).unwrap_err().contains("nested too deeply"));
+        assert_eq!(64, eval(&format!("{}1", "1 + ".repeat(63)), &CANMessage::new()));
+    }
+
+    #[test]
+    fn detects_references_to_request() {
+        assert!(!Expression::parse("(1 + 2) << 4").unwrap().uses_request());
+        assert!(Expression::parse("~(1 + req.data[0])").unwrap().uses_request());
+    }
+
+    #[test]
+    fn keeps_text_of_template_when_serializing() {
+        for json in &[r#""0x62""#, r#""${req.data[1] + 0x40}""#] {
+            let t: ValueTemplate = from_json(json);
+            assert_eq!(*json, serde_json::to_string(&t).unwrap());
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde_derive::*;
+
+use crate::can::{dlc_to_length, CANMessage, MAX_CLASSIC_LENGTH};
+use crate::error::ValidationError;
+use crate::literal;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FaultType {
+    #[serde(rename = "drop")]       Drop,
+    // flips random bits in the data
+    #[serde(rename = "corrupt")]    Corrupt,
+    // sends the frame with a different DLC, keeping the data
+    #[serde(rename = "flipDlc")]    FlipDlc,
+    #[serde(rename = "duplicate")]  Duplicate,
+    // sends the frame with a random id that is different from the intended one
+    #[serde(rename = "wrongId")]    WrongId,
+}
+
+// A fault that is injected into a response with the given probability. With a seed the same
+// sequence of faults is injected every time the stub is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fault {
+    #[serde(rename = "type")]
+    pub fault_type: FaultType,
+    pub rate: f64,
+    // number of bits flipped by the corrupt fault, defaults to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bits: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip)]
+    rng: Option<StdRng>,
+}
+
+impl Fault {
+    // Changes the messages that are sent for a response, which are none when the response was
+    // dropped, or more than one when it was duplicated. Returns whether the fault was injected.
+    pub fn apply_to(&mut self, messages: &mut Vec<CANMessage>) -> bool {
+        let seed = self.seed;
+        let rng = self.rng.get_or_insert_with(|| match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        });
+        if messages.is_empty() || !rng.gen_bool(self.rate) {
+            return false;
+        }
+        match self.fault_type {
+            FaultType::Drop => messages.clear(),
+            FaultType::Duplicate => messages.push(messages[0]),
+            FaultType::Corrupt => {
+                for m in messages.iter_mut() {
+                    corrupt(m, self.bits.unwrap_or(1), rng);
+                }
+            }
+            FaultType::FlipDlc => {
+                for m in messages.iter_mut() {
+                    flip_dlc(m, rng);
+                }
+            }
+            FaultType::WrongId => {
+                for m in messages.iter_mut() {
+                    let max_id = literal::max_id(Some(m.format)) as u32;
+                    // adding a non-zero offset modulo the number of ids always results in another id
+                    m.id = ((m.id as u64 + rng.gen_range(1..=max_id as u64)) % (max_id as u64 + 1)) as u32;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if !(0.0..=1.0).contains(&self.rate) {
+            return Err(ValidationError::new("rate", "rate must be between 0 and 1".to_string()));
+        }
+        if let Some(bits) = self.bits {
+            if self.fault_type != FaultType::Corrupt {
+                return Err(ValidationError::new("bits", "bits can only be given for the corrupt fault".to_string()));
+            }
+            if bits == 0 {
+                return Err(ValidationError::new("bits", "corrupt fault must flip at least one bit".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// flips distinct bits; frames without data are not changed
+fn corrupt(message: &mut CANMessage, bits: usize, rng: &mut StdRng) {
+    let length = message.length as usize;
+    let mut positions: Vec<usize> = (0..length * 8).collect();
+    for _ in 0..bits.min(positions.len()) {
+        let pos = positions.swap_remove(rng.gen_range(0..positions.len()));
+        message.data[pos / 8] ^= 1 << (pos % 8);
+    }
+}
+
+fn flip_dlc(message: &mut CANMessage, rng: &mut StdRng) {
+    let max_dlc = if message.fd { 15 } else { MAX_CLASSIC_LENGTH as u8 };
+    let lengths: Vec<usize> = (0..=max_dlc).map(dlc_to_length).filter(|l| *l != message.length as usize).collect();
+    message.length = lengths[rng.gen_range(0..lengths.len())] as u8;
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::utils;
+
+    use super::*;
+
+    fn from_json(s: &str) -> Fault {
+        utils::from_json(s)
+    }
+
+    fn apply(fault: &mut Fault, message: CANMessage) -> Vec<CANMessage> {
+        let mut messages = vec![message];
+        fault.apply_to(&mut messages);
+        messages
+    }
+
+    #[test]
+    fn injects_faults_at_rate() {
+        let mut fault = from_json(r#"{ "type": "drop", "rate": 0.25, "seed": 7 }"#);
+        let count = (0..1000).filter(|_| apply(&mut fault, CANMessage::new()).is_empty()).count();
+        assert!(count > 180 && count < 320, "dropped {} messages", count);
+        let mut fault = from_json(r#"{ "type": "drop", "rate": 0 }"#);
+        assert!(!fault.apply_to(&mut vec![CANMessage::new()]));
+    }
+
+    #[test]
+    fn injects_same_faults_with_same_seed() {
+        let json = r#"{ "type": "corrupt", "rate": 0.5, "bits": 3, "seed": 42 }"#;
+        let (mut f1, mut f2) = (from_json(json), from_json(json));
+        let message = CANMessage::with_content(0x100, &[0; 8]);
+        for _ in 0..10 {
+            assert_eq!(apply(&mut f1, message)[0].data, apply(&mut f2, message)[0].data);
+        }
+    }
+
+    #[test]
+    fn corrupts_given_number_of_bits() {
+        let mut fault = from_json(r#"{ "type": "corrupt", "rate": 1, "bits": 3 }"#);
+        let messages = apply(&mut fault, CANMessage::with_content(0x100, &[0; 8]));
+        assert_eq!(3, messages[0].data.iter().map(|b| b.count_ones()).sum::<u32>());
+    }
+
+    #[test]
+    fn changes_dlc_id_or_number_of_messages() {
+        let message = CANMessage::with_content(0x7FF, &[0x01, 0x02]);
+        let messages = apply(&mut from_json(r#"{ "type": "flipDlc", "rate": 1 }"#), message);
+        assert_ne!(2, messages[0].length);
+        assert!(messages[0].length <= 8);
+        let messages = apply(&mut from_json(r#"{ "type": "wrongId", "rate": 1 }"#), message);
+        assert_ne!(0x7FF, messages[0].id);
+        assert!(messages[0].id <= 0x7FF);
+        let messages = apply(&mut from_json(r#"{ "type": "duplicate", "rate": 1 }"#), message);
+        assert_eq!(2, messages.len());
+    }
+
+    #[test]
+    fn rejects_rate_outside_of_zero_to_one() {
+        assert_eq!("rate", from_json(r#"{ "type": "drop", "rate": 1.5 }"#).validate().unwrap_err().path);
+        assert_eq!("bits", from_json(r#"{ "type": "drop", "rate": 1, "bits": 2 }"#).validate().unwrap_err().path);
+    }
+}
@@ -0,0 +1,184 @@
+use serde_derive::*;
+
+use crate::can::MAX_FD_LENGTH;
+use crate::error::ValidationError;
+
+
+// A bit field inside a data byte; bit 0 is the least significant bit of the byte
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BitField {
+    pub byte: usize,
+    #[serde(default)]
+    pub bit: u8,
+    #[serde(default = "BitField::default_length")]
+    pub length: u8,
+}
+
+impl BitField {
+    fn default_length() -> u8 {
+        8
+    }
+
+    fn mask(&self) -> u8 {
+        (0xFFu16 >> (8 - self.length)) as u8
+    }
+
+    fn max_value(&self) -> u64 {
+        self.mask() as u64
+    }
+
+    // bytes beyond the length of the data are ignored
+    fn write(&self, data: &mut [u8], value: u64) {
+        if let Some(byte) = data.get_mut(self.byte) {
+            let mask = self.mask() << self.bit;
+            *byte = (*byte & !mask) | (((value as u8) << self.bit) & mask);
+        }
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.byte >= MAX_FD_LENGTH {
+            return Err(ValidationError::new("byte", format!("byte {} is beyond the end of a frame", self.byte)));
+        }
+        if self.length == 0 || self.bit as usize + self.length as usize > 8 {
+            return Err(ValidationError::new("length", "bit field must have at least one bit and must fit into the byte".to_string()));
+        }
+        Ok(())
+    }
+}
+
+
+// A counter that is incremented with every response of a stub, e.g. an alive counter
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Counter {
+    #[serde(flatten)]
+    pub field: BitField,
+    // defaults to the number of values the bit field can hold
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modulo: Option<u64>,
+}
+
+impl Counter {
+    pub fn apply_to(&self, data: &mut [u8], count: u64) {
+        let modulo = self.modulo.unwrap_or(self.field.max_value() + 1);
+        self.field.write(data, count % modulo);
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.field.validate()?;
+        if let Some(modulo) = self.modulo {
+            if modulo == 0 || modulo > self.field.max_value() + 1 {
+                return Err(ValidationError::new("modulo", format!("modulo must be between 1 and {}", self.field.max_value() + 1)));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ChecksumType {
+    #[serde(rename = "xor")]       Xor,
+    #[serde(rename = "sum")]       Sum,
+    // CRC-8 SAE J1850, as in the AUTOSAR CRC8 profile
+    #[serde(rename = "crc8")]      Crc8,
+    // CRC-8 with polynomial 0x2F, as in the AUTOSAR CRC8H2F profile
+    #[serde(rename = "crc8h2f")]   Crc8H2F,
+}
+
+// A checksum over a range of data bytes, which is computed after all other fields are set. The
+// range defaults to all data bytes; the byte that holds the checksum is always left out.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checksum {
+    #[serde(rename = "type")]
+    pub checksum_type: ChecksumType,
+    #[serde(flatten)]
+    pub field: BitField,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<usize>,
+    // index of the first byte after the range
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<usize>,
+}
+
+impl Checksum {
+    pub fn apply_to(&self, data: &mut [u8]) {
+        let start = self.start.unwrap_or(0).min(data.len());
+        let end = self.end.unwrap_or(data.len()).min(data.len()).max(start);
+        let bytes: Vec<u8> = (start..end).filter(|i| *i != self.field.byte).map(|i| data[i]).collect();
+        let value = match self.checksum_type {
+            ChecksumType::Xor => bytes.iter().fold(0, |acc, b| acc ^ b),
+            ChecksumType::Sum => bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)),
+            ChecksumType::Crc8 => crc8(&bytes, 0x1D),
+            ChecksumType::Crc8H2F => crc8(&bytes, 0x2F),
+        };
+        self.field.write(data, value as u64);
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.field.validate()?;
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            if start >= end {
+                return Err(ValidationError::new("end", "end of checksum range must be after its start".to_string()));
+            }
+        }
+        Ok(())
+    }
+}
+
+// both AUTOSAR profiles use 0xFF as initial value and final XOR value
+pub fn crc8(bytes: &[u8], polynomial: u8) -> u8 {
+    let mut crc = 0xFFu8;
+    for b in bytes {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ polynomial } else { crc << 1 };
+        }
+    }
+    crc ^ 0xFF
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::from_json;
+
+    use super::*;
+
+    #[test]
+    fn computes_crc8_with_check_values_of_autosar_profiles() {
+        assert_eq!(0x4B, crc8(b"123456789", 0x1D));
+        assert_eq!(0xDF, crc8(b"123456789", 0x2F));
+        assert_eq!(0x59, crc8(&[0x00, 0x00, 0x00, 0x00], 0x1D));
+        assert_eq!(0x12, crc8(&[0x00, 0x00, 0x00, 0x00], 0x2F));
+    }
+
+    #[test]
+    fn writes_counter_into_bit_field_with_modulo() {
+        let counter: Counter = from_json(r#"{ "byte": 1, "bit": 4, "length": 4, "modulo": 15 }"#);
+        let mut data = [0xFF, 0x0A];
+        counter.apply_to(&mut data, 16);
+        assert_eq!([0xFF, 0x1A], data);
+        counter.apply_to(&mut data, 14);
+        assert_eq!([0xFF, 0xEA], data);
+    }
+
+    #[test]
+    fn leaves_checksum_byte_out_of_checksum() {
+        let checksum: Checksum = from_json(r#"{ "type": "xor", "byte": 0 }"#);
+        let mut data = [0xAA, 0x01, 0x02, 0x04];
+        checksum.apply_to(&mut data);
+        assert_eq!(0x07, data[0]);
+        let checksum: Checksum = from_json(r#"{ "type": "sum", "byte": 3, "start": 1, "end": 3 }"#);
+        let mut data = [0x80, 0xF0, 0x20, 0x00];
+        checksum.apply_to(&mut data);
+        assert_eq!(0x10, data[3]);
+    }
+
+    #[test]
+    fn rejects_bit_field_that_does_not_fit_into_byte() {
+        let counter: Counter = from_json(r#"{ "byte": 1, "bit": 6, "length": 4 }"#);
+        assert_eq!("length", counter.validate().unwrap_err().path);
+        let counter: Counter = from_json(r#"{ "byte": 1, "length": 4, "modulo": 17 }"#);
+        assert_eq!("modulo", counter.validate().unwrap_err().path);
+    }
+}
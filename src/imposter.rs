@@ -1,11 +1,21 @@
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::Read;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde_derive::*;
 
-use crate::can::{CANMessage, create_adaptor};
+use crate::can::{CANMessage, CANError, AdaptorConfig, ErrorClass, create_adaptor};
 use crate::can::CANAdaptor;
-use crate::controller::ImposterList;
+use crate::dbc::Dbc;
+use crate::controller::{ImposterList, PortState, PortStatus};
+use crate::error::{ImposterError, ValidationError};
+use crate::periodic::Periodic;
+use crate::response::Response;
 use crate::stub::Stub;
 use crate::utils;
 
@@ -13,68 +23,379 @@ use crate::utils;
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Imposter {
     pub id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub adaptor: Option<AdaptorConfig>,
+    // path of a DBC file, relative to the DBC directory, that defines the messages and signals used in signal predicates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dbc: Option<String>,
     #[serde(rename = "recordMessages")]
     pub record_messages: Option<bool>,
+    #[serde(rename = "recordLimit", skip_serializing_if = "Option::is_none")]
+    pub record_limit: Option<usize>,
     pub stubs: Vec<Stub>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub periodic: Vec<Periodic>,
     #[serde(skip_deserializing)]
-    pub messages: Vec<CANMessage>
+    pub messages: VecDeque<RecordedMessage>,
+    #[serde(rename = "droppedMessages", skip_deserializing)]
+    pub dropped_messages: u64,
+    #[serde(rename = "injectedFaults", skip_deserializing)]
+    pub injected_faults: u64,
+    #[serde(skip)]
+    next_seq: u64,
+    #[serde(skip)]
+    database: Option<Arc<Dbc>>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum Direction {
+    #[serde(rename = "in")]   Incoming,
+    #[serde(rename = "out")]  Outgoing,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RecordedMessage {
+    pub seq: u64,
+    pub direction: Direction,
+    // for incoming messages the stub that matched, for outgoing messages the stub that generated it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stub: Option<usize>,
+    // for outgoing messages the sequence number of the message they respond to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request: Option<u64>,
+    // for outgoing periodic messages the entry in the periodic list that generated it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub periodic: Option<usize>,
+    #[serde(flatten)]
+    pub message: CANMessage,
 }
 
 
 impl Imposter {
 
-    pub fn from_json(json: &str) -> Imposter {
-        utils::from_json(json)
+    // parses and validates the definition, and reads the DBC file from the current directory
+    pub fn from_json(json: &str) -> Result<Imposter, ImposterError> {
+        Imposter::from_json_with_dbc_dir(json, Path::new("."))
+    }
+
+    pub fn from_json_with_dbc_dir(json: &str, dbc_dir: &Path) -> Result<Imposter, ImposterError> {
+        let mut imposter: Imposter = utils::parse_json(json)?;
+        imposter.validate()?;
+        imposter.load_dbc(dbc_dir)?;
+        Ok(imposter)
     }
 
-    pub fn from_file(filename: &str) -> Imposter {
+    pub fn from_file(filename: &str, dbc_dir: &Path) -> Result<Imposter, ImposterError> {
         println!("Reading imposter from file: {}", filename);
-        let mut file = File::open(filename).expect(&format!("Failed to open file {}", filename));
+        let mut file = File::open(filename)?;
         let mut contents = String::new();
-        file.read_to_string(&mut contents).expect(&format!("Failed to read file {}", filename));
-        Imposter::from_json(&contents)
+        file.read_to_string(&mut contents)?;
+        Imposter::from_json_with_dbc_dir(&contents, dbc_dir)
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for (i, stub) in self.stubs.iter().enumerate() {
+            stub.validate().map_err(|e| e.within(&format!("stubs[{}]", i)))?;
+        }
+        for (i, p) in self.periodic.iter().enumerate() {
+            p.validate().map_err(|e| e.within(&format!("periodic[{}]", i)))?;
+        }
+        Ok(())
+    }
+
+    pub fn load_dbc(&mut self, dbc_dir: &Path) -> Result<(), ValidationError> {
+        self.database = match &self.dbc {
+            Some(filename) => Some(Arc::new(Dbc::from_file(dbc_dir, filename).map_err(|e| ValidationError::new("dbc", e))?)),
+            None => None
+        };
+        let dbc = self.database.clone();
+        for (i, stub) in self.stubs.iter_mut().enumerate() {
+            stub.resolve_signals(dbc.as_deref()).map_err(|e| e.within(&format!("stubs[{}]", i)))?;
+        }
+        for (i, p) in self.periodic.iter_mut().enumerate() {
+            p.resolve_signals(dbc.as_deref()).map_err(|e| e.within(&format!("periodic[{}]", i)))?;
+        }
+        Ok(())
+    }
+
+    // looks up the signals used by a stub that is added to the imposter after it was loaded
+    pub fn resolve_signals(&self, stub: &mut Stub) -> Result<(), ValidationError> {
+        stub.resolve_signals(self.database.as_deref())
+    }
+
+    pub fn resolve_periodic_signals(&self, periodic: &mut Periodic) -> Result<(), ValidationError> {
+        periodic.resolve_signals(self.database.as_deref())
+    }
+
+    pub fn received_messages(&self) -> Vec<&CANMessage> {
+        self.messages.iter().filter(|m| m.direction == Direction::Incoming).map(|m| &m.message).collect()
+    }
+
+    pub fn sent_messages(&self) -> Vec<&CANMessage> {
+        self.messages.iter().filter(|m| m.direction == Direction::Outgoing).map(|m| &m.message).collect()
+    }
+
+    pub fn responses_to_message(&mut self, message: &CANMessage) -> Vec<Response> {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let stub_idx = self.stubs.iter().position(|s| s.matches_message(message));
+        self.record(RecordedMessage { seq, direction: Direction::Incoming, stub: stub_idx, request: None, periodic: None, message: *message });
+        match stub_idx {
+            Some(i) => {
+                let responses = self.stubs[i].generate_responses(message).into_iter()
+                    .map(|(m, delay)| Response { message: m, stub: Some(i), periodic: None, request: Some(seq), delay })
+                    .collect();
+                self.injected_faults += self.stubs[i].take_injected_faults();
+                responses
+            }
+            None => Vec::new()
+        }
+    }
+
+    pub fn record_sent(&mut self, response: &Response) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let mut message = response.message;
+        message.timestamp = Some(utils::monotonic_micros());
+        self.record(RecordedMessage { seq, direction: Direction::Outgoing, stub: response.stub, request: response.request, periodic: response.periodic, message });
+    }
+
+    // when the next periodic message is due; None if there are no enabled periodic messages
+    pub fn next_periodic_due(&mut self, now: Instant) -> Option<Instant> {
+        self.periodic.iter_mut().filter_map(|p| p.next_due(now)).min()
+    }
+
+    pub fn due_periodic_messages(&mut self, now: Instant) -> Vec<Response> {
+        self.periodic.iter_mut().enumerate()
+            .filter_map(|(i, p)| p.poll(now).map(|m| Response { message: m, stub: None, periodic: Some(i), request: None, delay: Duration::from_millis(0) }))
+            .collect()
+    }
+
+    // the index defaults to the end of the list; returns None if the index is out of range
+    pub fn insert_stub(&mut self, index: Option<usize>, stub: Stub) -> Option<usize> {
+        let index = index.unwrap_or(self.stubs.len());
+        if index > self.stubs.len() {
+            return None;
+        }
+        self.stubs.insert(index, stub);
+        Some(index)
+    }
+
+    pub fn replace_stub(&mut self, index: usize, stub: Stub) -> bool {
+        match self.stubs.get_mut(index) {
+            Some(s) => {
+                *s = stub;
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn remove_stub(&mut self, index: usize) -> bool {
+        if index >= self.stubs.len() {
+            return false;
+        }
+        self.stubs.remove(index);
+        true
+    }
+
+    // the index defaults to the end of the list; returns None if the index is out of range
+    pub fn insert_periodic(&mut self, index: Option<usize>, periodic: Periodic) -> Option<usize> {
+        let index = index.unwrap_or(self.periodic.len());
+        if index > self.periodic.len() {
+            return None;
+        }
+        self.periodic.insert(index, periodic);
+        Some(index)
+    }
+
+    pub fn replace_periodic(&mut self, index: usize, periodic: Periodic) -> bool {
+        match self.periodic.get_mut(index) {
+            Some(p) => {
+                *p = periodic;
+                true
+            }
+            None => false
+        }
+    }
+
+    pub fn remove_periodic(&mut self, index: usize) -> bool {
+        if index >= self.periodic.len() {
+            return false;
+        }
+        self.periodic.remove(index);
+        true
     }
 
-    pub fn received_messages(&mut self) -> &Vec<CANMessage> {
-        &self.messages
+    pub fn clear_messages(&mut self) {
+        self.messages.clear();
+        self.dropped_messages = 0;
+        self.injected_faults = 0;
     }
 
-    pub fn responses_to_message(&mut self, message: &CANMessage) -> Vec<CANMessage> {
+    // when the record limit is reached the oldest messages are dropped
+    fn record(&mut self, message: RecordedMessage) {
         if let Some(true) = self.record_messages {
-            self.messages.push(message.clone());
+            self.messages.push_back(message);
+            let limit = self.record_limit.unwrap_or(usize::MAX);
+            while self.messages.len() > limit {
+                self.messages.pop_front();
+                self.dropped_messages += 1;
+            }
         }
-        for i in 0..(self.stubs.len()) {
-            let stub = &mut self.stubs[i];
-            if stub.matches_message(message) {
-                return stub.generate_responses(message);
+    }
+}
+
+
+// how long the imposter loop waits for a message before checking whether it should stop
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+const MIN_RECEIVE_TIMEOUT: Duration = Duration::from_millis(1);
+// how long the port waits before initializing a failed adaptor again; doubles with every failure
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+// after this many transient errors in a row the adaptor is initialized again
+const MAX_TRANSIENT_ERRORS: u32 = 10;
+
+// Runs the imposter loop for a port until it is stopped. When the adaptor fails, or cannot be
+// initialized in the first place, it is initialized again after a backoff period.
+pub fn run(id: u32, list: ImposterList, config: &AdaptorConfig, stop: &AtomicBool, status: &Mutex<PortStatus>) {
+    let mut backoff = INITIAL_BACKOFF;
+    let mut is_first_attempt = true;
+    while !stop.load(Ordering::SeqCst) {
+        if !is_first_attempt {
+            status.lock().unwrap().restarts += 1;
+        }
+        is_first_attempt = false;
+        let started_at = Instant::now();
+        match create_adaptor(config) {
+            Ok(mut adaptor) => {
+                if let Err(error) = run_with_adaptor(id, list.clone(), adaptor.as_mut(), stop, status) {
+                    println!("Re-initializing CAN adaptor {} for imposter {} after error: {}", config.adaptor_type, id, error);
+                }
+            }
+            Err(error) => {
+                println!("Failed to initialize CAN adaptor {} for imposter {}: {}", config.adaptor_type, id, error);
+                status.lock().unwrap().record_error(&error);
             }
         }
-        Vec::new()
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        status.lock().unwrap().state = PortState::Recovering;
+        if started_at.elapsed() > MAX_BACKOFF {
+            backoff = INITIAL_BACKOFF;
+        }
+        sleep_unless_stopped(backoff, stop);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
     }
+    status.lock().unwrap().state = PortState::Stopped;
 }
 
+// Responses that have to wait before they are sent, ordered by when they are due. Keeping them
+// here, instead of waiting in the stub, means that the port keeps receiving messages, and that the
+// imposter list is not locked while the response waits.
+#[derive(Default)]
+struct TransmitQueue {
+    entries: VecDeque<(Instant, Response)>,
+}
 
-pub fn run(id: u32, list: ImposterList) {
-    let mut adaptor = create_adaptor().expect("Failed to initialize CAN device.");
-    run_with_adaptor(id, list, adaptor.as_mut());
+impl TransmitQueue {
+    // responses that are due at the same time keep their order
+    fn push(&mut self, due: Instant, response: Response) {
+        let index = self.entries.iter().position(|(t, _)| *t > due).unwrap_or(self.entries.len());
+        self.entries.insert(index, (due, response));
+    }
+
+    fn next_due(&self) -> Option<Instant> {
+        self.entries.front().map(|(t, _)| *t)
+    }
+
+    fn pop_due(&mut self, now: Instant) -> Vec<Response> {
+        let count = self.entries.iter().take_while(|(t, _)| *t <= now).count();
+        self.entries.drain(..count).map(|(_, r)| r).collect()
+    }
 }
 
-// mostly extracted from above to allow for testing with mock from integration test
-pub fn run_with_adaptor(id: u32, mut list: ImposterList, adaptor: &mut CANAdaptor) {
-    loop {
-        match adaptor.receive() {
-            Ok(message) => {
+// Runs the imposter loop with the given adaptor until the port is stopped, which returns Ok, or
+// until the adaptor has to be initialized again, which returns the error that caused this.
+pub fn run_with_adaptor(id: u32, mut list: ImposterList, adaptor: &mut dyn CANAdaptor, stop: &AtomicBool, status: &Mutex<PortStatus>) -> Result<(), CANError> {
+    status.lock().unwrap().state = PortState::Running;
+    let mut transient_errors = 0;
+    let mut queue = TransmitQueue::default();
+    while !stop.load(Ordering::SeqCst) {
+        // the receive timeout is shortened so that delayed and periodic messages are sent on time
+        let now = Instant::now();
+        let mut next_due = queue.next_due();
+        list.do_with_imposter_by_id(id, |imposter| {
+            next_due = next_due.into_iter().chain(imposter.next_periodic_due(now)).min();
+        });
+        let timeout = next_due.map_or(RECEIVE_TIMEOUT, |due| {
+            RECEIVE_TIMEOUT.min(due.saturating_duration_since(now)).max(MIN_RECEIVE_TIMEOUT)
+        });
+        match adaptor.receive(timeout) {
+            Ok(Some(mut message)) => {
+                transient_errors = 0;
+                if message.timestamp.is_none() {
+                    message.timestamp = Some(utils::monotonic_micros());
+                }
+                let mut send_errors = Vec::new();
                 list.do_with_imposter_by_id(id, |imposter| {
-                    for response in imposter.responses_to_message(&message) {
-                        adaptor.send(&response).expect("Failed to send CAN message.");
+                    let (now, later): (Vec<Response>, Vec<Response>) = imposter.responses_to_message(&message)
+                        .into_iter().partition(|r| r.delay == Duration::from_millis(0));
+                    send_responses(adaptor, imposter, now, &mut send_errors);
+                    for response in later {
+                        queue.push(Instant::now() + response.delay, response);
                     }
                 });
+                for error in send_errors {
+                    println!("Failed to send CAN message: {}", error);
+                    handle_error(error, status, &mut transient_errors)?;
+                }
             }
-            Err(errmsg) => {
-                println!("Failed to receive CAN message: {}", errmsg);
-                break;
+            Ok(None) => {}
+            Err(error) => {
+                println!("Failed to receive CAN message: {}", error);
+                handle_error(error, status, &mut transient_errors)?;
+                sleep_unless_stopped(INITIAL_BACKOFF * transient_errors, stop);
             }
         };
+        let mut send_errors = Vec::new();
+        list.do_with_imposter_by_id(id, |imposter| {
+            let now = Instant::now();
+            let mut messages = queue.pop_due(now);
+            messages.extend(imposter.due_periodic_messages(now));
+            send_responses(adaptor, imposter, messages, &mut send_errors);
+        });
+        for error in send_errors {
+            println!("Failed to send CAN message: {}", error);
+            handle_error(error, status, &mut transient_errors)?;
+        }
+    }
+    Ok(())
+}
+
+fn send_responses(adaptor: &mut dyn CANAdaptor, imposter: &mut Imposter, responses: Vec<Response>, errors: &mut Vec<CANError>) {
+    for response in responses {
+        match adaptor.send(&response.message) {
+            Ok(()) => imposter.record_sent(&response),
+            Err(error) => errors.push(error)
+        }
+    }
+}
+
+fn handle_error(error: CANError, status: &Mutex<PortStatus>, transient_errors: &mut u32) -> Result<(), CANError> {
+    status.lock().unwrap().record_error(&error);
+    *transient_errors += 1;
+    if error.class() != ErrorClass::Transient || *transient_errors > MAX_TRANSIENT_ERRORS {
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn sleep_unless_stopped(duration: Duration, stop: &AtomicBool) {
+    let until = Instant::now() + duration;
+    while !stop.load(Ordering::SeqCst) && Instant::now() < until {
+        thread::sleep(until.saturating_duration_since(Instant::now()).min(RECEIVE_TIMEOUT));
     }
 }
 
@@ -92,12 +413,25 @@ mod tests {
                   "predicates": [{ "eq": { "id": "0x200" } }],
                   "responses": [{ "id": "0x0201", "data": [ "0x01" ] }]
                 }
-            ]}"#);
+            ]}"#).unwrap();
 
         assert_eq!(12, imposter.id);
         assert_eq!(1, imposter.stubs.len());
     }
 
+    #[test]
+    fn reads_adaptor_config_from_json_definition() {
+        let imposter = Imposter::from_json(r#"{
+            "id": 0,
+            "adaptor": { "type": "socketcan", "interface": "vcan0" },
+            "stubs": []
+            }"#).unwrap();
+
+        let config = imposter.adaptor.unwrap();
+        assert_eq!("socketcan", config.adaptor_type);
+        assert_eq!(Some("vcan0".to_string()), config.option("interface"));
+    }
+
     #[test]
     fn returns_response_from_first_matching_stub() {
         let mut imposter = Imposter::from_json(r#"{
@@ -113,22 +447,87 @@ mod tests {
                 },
                 {
                     "predicates": [{ "eq": { "id": "*" } }],
-                    "responses": [{ "id": "0xFFFF", "data": [ "0x12" ] }]
+                    "responses": [{ "id": "0x7FF", "data": [ "0x12" ] }]
                 }
-            ]}"#);
+            ]}"#).unwrap();
 
-        let message = CANMessage::with_content(0x202, 0, &[ 0x00 ]);
+        let message = CANMessage::with_content(0x202, &[ 0x00 ]);
 
         let responses = imposter.responses_to_message(&message);
 
         assert_eq!(1, responses.len());
-        assert_eq!(0x202, responses[0].id);
+        assert_eq!(0x202, responses[0].message.id);
+        assert_eq!(Some(1), responses[0].stub);
+    }
+
+    #[test]
+    fn fails_with_path_of_invalid_element_when_stub_is_invalid() {
+        let result = Imposter::from_json(r#"{
+            "id": 1,
+            "stubs": [
+                {
+                    "predicates": [{ "eq": { "id": "0x202" } }],
+                    "responses": [{ "id": "0x0202", "data": [ "0x12" ] }]
+                },
+                {
+                    "predicates": [{ "eq": { "id": "0x203" } }],
+                    "responses": [{ "id": "0x0203", "data": [ "0", "1", "2", "3", "4", "5", "6", "7", "8" ] }]
+                }
+            ]}"#);
+
+        assert_eq!(Some("stubs[1].responses[0].data[8]"), result.unwrap_err().path());
+    }
+
+    #[test]
+    fn fails_with_path_of_element_that_does_not_match_schema() {
+        let result = Imposter::from_json(r#"{
+            "id": 1,
+            "stubs": [
+                {
+                    "predicates": [{ "eq": { "id": "0x202" } }],
+                    "responses": [{ "id": "0x0202", "data": [ 18 ] }]
+                }
+            ]}"#);
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, ImposterError::Schema { .. }));
+        assert_eq!(Some("stubs[0].responses[0].data[0]"), error.path());
+    }
+
+    #[test]
+    fn fails_with_path_of_dbc_when_file_cannot_be_read() {
+        let result = Imposter::from_json(r#"{ "id": 1, "dbc": "no-such-file.dbc", "stubs": [] }"#);
+
+        assert_eq!(Some("dbc"), result.unwrap_err().path());
+    }
+
+    #[test]
+    fn fails_when_signal_predicate_is_used_without_dbc() {
+        let result = Imposter::from_json(r#"{
+            "id": 1,
+            "stubs": [
+                {
+                    "predicates": [{ "signal": { "message": "EEC1", "name": "EngineSpeed", "gt": 3000 } }],
+                    "responses": [{ "id": "0x0202", "data": [ "0x12" ] }]
+                }
+            ]}"#);
+
+        assert_eq!(Some("stubs[0].predicates[0].signal"), result.unwrap_err().path());
+    }
+
+    #[test]
+    fn fails_without_path_when_json_is_invalid() {
+        let result = Imposter::from_json(r#"{ "id": 1, "stubs": [ }"#);
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, ImposterError::Syntax(_)));
+        assert_eq!(None, error.path());
     }
 
     #[test]
     fn does_not_record_received_messages_by_default() {
-        let mut imposter = Imposter::from_json(r#"{ "id": 0, "stubs": [] }"#);
-        let message = CANMessage::with_content(0x202, 0, &[ 0x00 ]);
+        let mut imposter = Imposter::from_json(r#"{ "id": 0, "stubs": [] }"#).unwrap();
+        let message = CANMessage::with_content(0x202, &[ 0x00 ]);
 
         imposter.responses_to_message(&message);
 
@@ -139,8 +538,8 @@ mod tests {
 
     #[test]
     fn records_received_messages_when_instructed() {
-        let mut imposter = Imposter::from_json(r#"{ "id": 0, "recordMessages": true, "stubs": [] }"#);
-        let message = CANMessage::with_content(0x202, 0, &[ 0x00 ]);
+        let mut imposter = Imposter::from_json(r#"{ "id": 0, "recordMessages": true, "stubs": [] }"#).unwrap();
+        let message = CANMessage::with_content(0x202, &[ 0x00 ]);
 
         imposter.responses_to_message(&message);
 
@@ -150,5 +549,116 @@ mod tests {
         assert_eq!(0x202, received[0].id);
     }
 
-}
+    #[test]
+    fn records_sent_messages_with_stub_and_request() {
+        let mut imposter = Imposter::from_json(r#"{
+            "id": 0,
+            "recordMessages": true,
+            "stubs": [
+                {
+                    "predicates": [{ "eq": { "id": "0x201" } }],
+                    "responses": [{ "id": "0x0301", "data": [ "0x12" ] }]
+                },
+                {
+                    "predicates": [{ "eq": { "id": "0x202" } }],
+                    "responses": [{ "id": "0x0302", "data": [ "0x12" ] }]
+                }
+            ]}"#).unwrap();
+        let message = CANMessage::with_content(0x202, &[ 0x00 ]);
+
+        for response in imposter.responses_to_message(&message) {
+            imposter.record_sent(&response);
+        }
+
+        assert_eq!(2, imposter.messages.len());
+        let incoming = &imposter.messages[0];
+        assert_eq!(Direction::Incoming, incoming.direction);
+        assert_eq!(Some(1), incoming.stub);
+        let outgoing = &imposter.messages[1];
+        assert_eq!(Direction::Outgoing, outgoing.direction);
+        assert_eq!(0x302, outgoing.message.id);
+        assert_eq!(Some(1), outgoing.stub);
+        assert_eq!(Some(incoming.seq), outgoing.request);
+        assert_eq!(true, outgoing.message.timestamp.is_some());
+        assert_eq!(1, imposter.sent_messages().len());
+    }
+
+    #[test]
+    fn drops_oldest_messages_when_record_limit_is_reached() {
+        let mut imposter = Imposter::from_json(r#"{ "id": 0, "recordMessages": true, "recordLimit": 2, "stubs": [] }"#).unwrap();
+
+        for id in 1..4 {
+            imposter.responses_to_message(&CANMessage::with_content(id, &[]));
+        }
+
+        assert_eq!(2, imposter.messages.len());
+        assert_eq!(2, imposter.messages[0].message.id);
+        assert_eq!(3, imposter.messages[1].message.id);
+        assert_eq!(1, imposter.dropped_messages);
+    }
+
+    #[test]
+    fn clears_recorded_messages_and_dropped_count() {
+        let mut imposter = Imposter::from_json(r#"{ "id": 0, "recordMessages": true, "recordLimit": 1, "stubs": [] }"#).unwrap();
+        imposter.responses_to_message(&CANMessage::with_content(0x01, &[]));
+        imposter.responses_to_message(&CANMessage::with_content(0x02, &[]));
+
+        imposter.clear_messages();
+
+        assert_eq!(0, imposter.messages.len());
+        assert_eq!(0, imposter.dropped_messages);
+    }
+
+    #[test]
+    fn inserts_stub_without_resetting_state_of_other_stubs() {
+        let mut imposter = Imposter::from_json(r#"{
+            "id": 0,
+            "stubs": [
+                {
+                    "predicates": [{ "eq": { "id": "0x201" } }],
+                    "responses": [{ "id": "0x0301", "data": [ ] }, { "id": "0x0302", "data": [ ] }]
+                }
+            ]}"#).unwrap();
+        let message = CANMessage::with_content(0x201, &[]);
+        imposter.responses_to_message(&message);
+        let stub: Stub = utils::from_json(r#"{
+                    "predicates": [{ "eq": { "id": "0x202" } }],
+                    "responses": [{ "id": "0x0303", "data": [ ] }]
+                }"#);
+
+        assert_eq!(Some(0), imposter.insert_stub(Some(0), stub));
+
+        let responses = imposter.responses_to_message(&message);
+        assert_eq!(0x302, responses[0].message.id);
+        assert_eq!(Some(1), responses[0].stub);
+    }
+
+    #[test]
+    fn does_not_insert_stub_beyond_end_of_list() {
+        let mut imposter = Imposter::from_json(r#"{ "id": 0, "stubs": [] }"#).unwrap();
+        let stub: Stub = utils::from_json(r#"{ "predicates": [], "responses": [{ "id": "0x01", "data": [ ] }] }"#);
+
+        assert_eq!(None, imposter.insert_stub(Some(1), stub.clone()));
+        assert_eq!(Some(0), imposter.insert_stub(None, stub));
+    }
 
+    #[test]
+    fn replaces_and_removes_stubs_by_index() {
+        let mut imposter = Imposter::from_json(r#"{
+            "id": 0,
+            "stubs": [
+                { "predicates": [], "responses": [{ "id": "0x01", "data": [ ] }] },
+                { "predicates": [], "responses": [{ "id": "0x02", "data": [ ] }] }
+            ]}"#).unwrap();
+        let stub: Stub = utils::from_json(r#"{ "predicates": [], "responses": [{ "id": "0x03", "data": [ ] }] }"#);
+
+        assert_eq!(true, imposter.replace_stub(0, stub.clone()));
+        assert_eq!(false, imposter.replace_stub(2, stub));
+        assert_eq!(true, imposter.remove_stub(1));
+        assert_eq!(false, imposter.remove_stub(1));
+
+        let responses = imposter.responses_to_message(&CANMessage::with_content(0x01, &[]));
+        assert_eq!(0x03, responses[0].message.id);
+    }
+
+}
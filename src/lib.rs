@@ -12,9 +12,16 @@ pub mod predicate;
 pub mod response;
 pub mod can;
 pub mod utils;
+pub mod error;
+pub mod literal;
+pub mod dbc;
+pub mod expression;
+pub mod fault;
+pub mod generator;
+pub mod periodic;
 pub mod webapi;
 
-pub fn run(imposter_files: Vec<String>)
+pub fn run(imposter_files: Vec<String>, default_adaptor: can::AdaptorConfig, dbc_dir: std::path::PathBuf)
 {
-    controller::run(imposter_files);
+    controller::run(imposter_files, default_adaptor, dbc_dir);
 }
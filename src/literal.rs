@@ -0,0 +1,282 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{self, Error, MapAccess, Visitor};
+use serde::de::value::MapAccessDeserializer;
+use serde_derive::*;
+
+use crate::can::FrameFormat;
+use crate::error::ValidationError;
+
+
+pub const MAX_STANDARD_ID: u64 = 0x7FF;
+pub const MAX_EXTENDED_ID: u64 = 0x1FFF_FFFF;
+pub const MAX_BYTE: u64 = 0xFF;
+
+
+pub fn parse_number(string: &str) -> Result<u64, String> {
+    let result = match string.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => string.parse::<u64>()
+    };
+    result.map_err(|_| format!("invalid number {:?}; expected decimal or hexadecimal (0x...) number", string))
+}
+
+
+// A number that is written as a decimal or hexadecimal string in a definition, e.g. "0x1F". The
+// string is parsed when the definition is read, and it is kept so that the definition can be
+// returned in the same form it was posted in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Number {
+    pub value: u64,
+    text: String,
+}
+
+impl FromStr for Number {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Number, String> {
+        parse_number(text).map(|value| Number { value, text: text.to_string() })
+    }
+}
+
+impl Number {
+    pub fn check_max(&self, path: &str, max: u64, what: &str) -> Result<(), ValidationError> {
+        if self.value > max {
+            return Err(ValidationError::new(path, format!("{} is out of range for {}; must not be larger than 0x{:X}", self.text, what, max)));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for Number {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Number {
+    fn deserialize<D>(deserializer: D) -> Result<Number, D::Error> where D: Deserializer<'de> {
+        let text = String::deserialize(deserializer)?;
+        text.parse::<Number>().map_err(D::Error::custom)
+    }
+}
+
+
+// Parses a bit pattern like 0b1xx0_0x1x, in which an x matches either bit value and underscores
+// can be used to group bits. Returns the value and the mask of the bits that have to match.
+pub fn parse_bits(string: &str) -> Result<(u64, u64), String> {
+    let error = || format!("invalid bit pattern {:?}; expected 0b followed by 0, 1, x, and _", string);
+    if !string.starts_with("0b") {
+        return Err(error());
+    }
+    let bits: Vec<char> = string[2..].chars().filter(|c| *c != '_').collect();
+    if bits.is_empty() || bits.len() > 64 {
+        return Err(error());
+    }
+    let (mut value, mut mask) = (0u64, 0u64);
+    for c in bits {
+        value <<= 1;
+        mask <<= 1;
+        match c {
+            '0' => { mask |= 1; }
+            '1' => { mask |= 1; value |= 1; }
+            'x' | 'X' => {}
+            _ => return Err(error())
+        }
+    }
+    Ok((value, mask))
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MaskedValue {
+    pub value: Number,
+    pub mask: Number,
+}
+
+// A number, an asterisk that matches any value, a bit pattern, or a value and a mask that selects
+// the bits that have to match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    Any,
+    Value(Number),
+    Bits { value: u64, mask: u64, text: String },
+    Masked(MaskedValue),
+}
+
+impl Pattern {
+    pub fn matches(&self, value: u64) -> bool {
+        match self {
+            Pattern::Any => true,
+            Pattern::Value(n) => n.value == value,
+            Pattern::Bits { value: v, mask, .. } => value & mask == v & mask,
+            Pattern::Masked(m) => value & m.mask.value == m.value.value & m.mask.value,
+        }
+    }
+
+    pub fn check_max(&self, path: &str, max: u64, what: &str) -> Result<(), ValidationError> {
+        match self {
+            Pattern::Any => Ok(()),
+            Pattern::Value(n) => n.check_max(path, max, what),
+            Pattern::Bits { mask, text, .. } => {
+                if *mask > max {
+                    return Err(ValidationError::new(path, format!("{} has too many bits for {}; must not be larger than 0x{:X}", text, what, max)));
+                }
+                Ok(())
+            }
+            Pattern::Masked(m) => {
+                m.value.check_max(&format!("{}.value", path), max, what)?;
+                m.mask.check_max(&format!("{}.mask", path), max, what)
+            }
+        }
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match self {
+            Pattern::Any => serializer.serialize_str("*"),
+            Pattern::Value(n) => n.serialize(serializer),
+            Pattern::Bits { text, .. } => serializer.serialize_str(text),
+            Pattern::Masked(m) => m.serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Pattern, D::Error> where D: Deserializer<'de> {
+        deserializer.deserialize_any(PatternVisitor)
+    }
+}
+
+struct PatternVisitor;
+
+impl<'de> Visitor<'de> for PatternVisitor {
+    type Value = Pattern;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a number, *, a bit pattern, or an object with value and mask")
+    }
+
+    fn visit_str<E>(self, text: &str) -> Result<Pattern, E> where E: de::Error {
+        if text == "*" {
+            Ok(Pattern::Any)
+        } else if text.starts_with("0b") {
+            let (value, mask) = parse_bits(text).map_err(E::custom)?;
+            Ok(Pattern::Bits { value, mask, text: text.to_string() })
+        } else {
+            text.parse::<Number>().map(Pattern::Value).map_err(E::custom)
+        }
+    }
+
+    fn visit_map<A>(self, map: A) -> Result<Pattern, A::Error> where A: MapAccess<'de> {
+        MaskedValue::deserialize(MapAccessDeserializer::new(map)).map(Pattern::Masked)
+    }
+}
+
+
+// when the format is not known an id can have up to 29 bits
+pub fn max_id(format: Option<FrameFormat>) -> u64 {
+    match format {
+        Some(FrameFormat::Standard) => MAX_STANDARD_ID,
+        _ => MAX_EXTENDED_ID,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::from_json;
+
+    use super::*;
+
+    #[test]
+    fn parses_decimal_and_hexadecimal_numbers() {
+        assert_eq!(Ok(256), parse_number("256"));
+        assert_eq!(Ok(0x1F), parse_number("0x1F"));
+    }
+
+    #[test]
+    fn fails_to_parse_invalid_numbers() {
+        assert!(parse_number("0xZZ").is_err());
+        assert!(parse_number("").is_err());
+    }
+
+    #[test]
+    fn keeps_text_of_number_when_serializing() {
+        let n: Number = from_json(r#""0x017""#);
+        assert_eq!(0x17, n.value);
+        assert_eq!(r#""0x017""#, serde_json::to_string(&n).unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_number_when_deserializing() {
+        assert!(serde_json::from_str::<Number>(r#""0xZZ""#).is_err());
+    }
+
+    #[test]
+    fn pattern_matches_value_when_number_is_equal() {
+        let p: Pattern = from_json(r#""256""#);
+        assert_eq!(true, p.matches(0x0100));
+        assert_eq!(false, p.matches(0x0101));
+    }
+
+    #[test]
+    fn pattern_matches_any_value_when_pattern_is_asterisk() {
+        let p: Pattern = from_json(r#""*""#);
+        assert_eq!(Pattern::Any, p);
+        assert_eq!(true, p.matches(0x0101));
+    }
+
+    #[test]
+    fn pattern_matches_value_when_bits_in_bit_pattern_match() {
+        let p: Pattern = from_json(r#""0b1xx0_0x1x""#);
+        assert_eq!(true, p.matches(0b1000_0010));
+        assert_eq!(true, p.matches(0b1110_0111));
+        assert_eq!(false, p.matches(0b0000_0010));
+        assert_eq!(false, p.matches(0b1000_1010));
+    }
+
+    #[test]
+    fn pattern_matches_value_when_masked_bits_match() {
+        let p: Pattern = from_json(r#"{ "value": "0x08", "mask": "0x08" }"#);
+        assert_eq!(true, p.matches(0x0F));
+        assert_eq!(false, p.matches(0xF7));
+    }
+
+    #[test]
+    fn keeps_form_of_pattern_when_serializing() {
+        for json in &[r#""*""#, r#""0x1F""#, r#""0b1xx0_0x1x""#, r#"{"value":"0x08","mask":"0x08"}"#] {
+            let p: Pattern = from_json(json);
+            assert_eq!(*json, serde_json::to_string(&p).unwrap());
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_bit_patterns() {
+        assert!(parse_bits("0b").is_err());
+        assert!(parse_bits("0b10z1").is_err());
+        assert!(serde_json::from_str::<Pattern>(r#""0b12""#).is_err());
+    }
+
+    #[test]
+    fn fails_check_when_bit_pattern_is_too_long() {
+        let p: Pattern = from_json(r#""0b1_0000_0000""#);
+        assert!(p.check_max("data[0]", MAX_BYTE, "a data byte").is_err());
+    }
+
+    #[test]
+    fn fails_check_when_value_is_too_large() {
+        let n: Number = "0x100".parse().unwrap();
+        assert!(n.check_max("data[0]", MAX_BYTE, "a data byte").is_err());
+        assert!(n.check_max("id", MAX_STANDARD_ID, "a standard id").is_ok());
+    }
+}
@@ -3,28 +3,55 @@ extern crate getopts;
 
 use getopts::Options;
 use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use candouble::can::{AdaptorConfig, AdaptorRegistry};
 
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
+    let registry = AdaptorRegistry::with_builtin_adaptors();
 
     let mut opts = Options::new();
 //    opts.optopt("o", "", "set output file name", "NAME");
+    opts.optopt("a", "adaptor", "set CAN adaptor used for imposters that don't specify one", "NAME");
+    opts.optmulti("o", "option", "set an option for the CAN adaptor, e.g. bitrate=500000", "KEY=VALUE");
+    opts.optopt("i", "interface", "shorthand for --option interface=NAME", "NAME");
+    opts.optopt("d", "dbc-dir", "set directory that DBC files of imposters are read from, defaults to the current directory", "DIR");
     opts.optflag("h", "help", "print this help menu");
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => { m }
-        Err(f) => { panic!(f.to_string()) }
+        Err(f) => { panic!("{}", f.to_string()) }
     };
     if matches.opt_present("h") {
-        print_usage(&program, opts);
+        print_usage(&program, opts, &registry);
         return;
     }
-    candouble::run(matches.free);
+
+    let adaptor_name = match matches.opt_str("a").or_else(|| registry.default_name()) {
+        Some(name) => name,
+        None => { eprintln!("No CAN adaptors included in this build."); process::exit(1); }
+    };
+    let mut adaptor = AdaptorConfig::new(&adaptor_name);
+    for option in matches.opt_strs("o") {
+        match option.find('=') {
+            Some(pos) => adaptor.set_option(&option[..pos], &option[(pos + 1)..]),
+            None => { eprintln!("Invalid adaptor option {}; expected KEY=VALUE", option); process::exit(1); }
+        }
+    }
+    if let Some(interface) = matches.opt_str("i") {
+        adaptor.set_option("interface", &interface);
+    }
+
+    let dbc_dir = PathBuf::from(matches.opt_str("d").unwrap_or_else(|| ".".to_string()));
+    candouble::run(matches.free, adaptor, dbc_dir);
 }
 
 
-fn print_usage(program: &str, opts: Options) {
+fn print_usage(program: &str, opts: Options, registry: &AdaptorRegistry) {
     let brief = format!("Usage: {} [options] files", program);
     print!("{}", opts.usage(&brief));
+    println!("\nAvailable adaptors: {}", registry.names().join(", "));
 }
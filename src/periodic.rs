@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde_derive::*;
+
+use crate::can::CANMessage;
+use crate::dbc::Dbc;
+use crate::error::ValidationError;
+use crate::response::ResponseTemplate;
+
+
+// A message that the imposter sends every cycle, independent of incoming messages. The first
+// message is sent offset milliseconds after the imposter is started or the entry is enabled,
+// and each message is delayed by a random time of up to jitter milliseconds. Delays caused by
+// the jitter do not accumulate.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Periodic {
+    #[serde(flatten)]
+    pub template: ResponseTemplate,
+    pub cycle: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jitter: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    // when the message is due without jitter, and when it is due with jitter
+    #[serde(skip)]
+    schedule: Option<(Instant, Instant)>,
+    #[serde(skip)]
+    count: u64,
+}
+
+impl Periodic {
+    pub fn is_enabled(&self) -> bool {
+        self.enabled != Some(false)
+    }
+
+    // returns when the next message is due, scheduling the first message if necessary
+    pub fn next_due(&mut self, now: Instant) -> Option<Instant> {
+        if !self.is_enabled() {
+            self.schedule = None;
+            return None;
+        }
+        if self.schedule.is_none() {
+            let nominal = now + Duration::from_millis(self.offset.unwrap_or(0));
+            self.schedule = Some((nominal, nominal + self.random_jitter()));
+        }
+        self.schedule.map(|(_, due)| due)
+    }
+
+    // returns the message if it is due, and schedules the next one
+    pub fn poll(&mut self, now: Instant) -> Option<CANMessage> {
+        let due = self.next_due(now)?;
+        if now < due {
+            return None;
+        }
+        let (nominal, _) = self.schedule?;
+        let cycle = Duration::from_millis(self.cycle);
+        // when the port could not keep up the schedule starts again instead of sending a burst
+        let next = if nominal + cycle < now { now } else { nominal + cycle };
+        self.schedule = Some((next, next + self.random_jitter()));
+        let message = self.template.generate_response(&CANMessage::new(), self.count);
+        self.count = self.count.wrapping_add(1);
+        Some(message)
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.template.validate()?;
+        self.template.validate_without_request()?;
+        if self.template.behaviors.is_some() {
+            return Err(ValidationError::new("_behaviors", "periodic messages cannot have behaviors".to_string()));
+        }
+        if self.cycle == 0 {
+            return Err(ValidationError::new("cycle", "cycle time must be at least 1ms".to_string()));
+        }
+        if self.jitter.is_some_and(|j| j >= self.cycle) {
+            return Err(ValidationError::new("jitter", "jitter must be less than the cycle time".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn resolve_signals(&mut self, dbc: Option<&Dbc>) -> Result<(), ValidationError> {
+        self.template.resolve_signals(dbc)
+    }
+
+    fn random_jitter(&self) -> Duration {
+        match self.jitter {
+            Some(jitter) if jitter > 0 => Duration::from_millis(rand::thread_rng().gen_range(0..=jitter)),
+            _ => Duration::from_millis(0)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::utils;
+
+    use super::*;
+
+    fn from_json(s: &str) -> Periodic {
+        utils::from_json(s)
+    }
+
+    #[test]
+    fn sends_message_every_cycle_after_offset() {
+        let mut p = from_json(r#"{ "id": "0x0101", "data": ["0x01"], "cycle": 100, "offset": 20 }"#);
+        let start = Instant::now();
+        assert_eq!(Some(start + Duration::from_millis(20)), p.next_due(start));
+        assert!(p.poll(start + Duration::from_millis(19)).is_none());
+        assert_eq!(0x0101, p.poll(start + Duration::from_millis(20)).unwrap().id);
+        assert!(p.poll(start + Duration::from_millis(100)).is_none());
+        assert!(p.poll(start + Duration::from_millis(125)).is_some());
+        assert_eq!(Some(start + Duration::from_millis(220)), p.next_due(start));
+    }
+
+    #[test]
+    fn sends_message_within_jitter_of_cycle() {
+        let mut p = from_json(r#"{ "id": "0x0101", "data": [], "cycle": 100, "jitter": 10 }"#);
+        let start = Instant::now();
+        let due = p.next_due(start).unwrap();
+        assert!(due >= start && due <= start + Duration::from_millis(10));
+        p.poll(due);
+        let due = p.next_due(start).unwrap();
+        assert!(due >= start + Duration::from_millis(100) && due <= start + Duration::from_millis(110));
+    }
+
+    #[test]
+    fn does_not_send_message_when_disabled() {
+        let mut p = from_json(r#"{ "id": "0x0101", "data": [], "cycle": 10, "enabled": false }"#);
+        assert!(p.poll(Instant::now()).is_none());
+    }
+
+    #[test]
+    fn rejects_jitter_that_is_not_less_than_cycle() {
+        let p = from_json(r#"{ "id": "0x0101", "data": [], "cycle": 10, "jitter": 10 }"#);
+        assert_eq!("jitter", p.validate().unwrap_err().path);
+    }
+
+    #[test]
+    fn rejects_expressions_that_refer_to_request() {
+        let p = from_json(r#"{ "id": "0x0101", "data": ["${1 + 2}", "${req.data[0]}"], "cycle": 10 }"#);
+        assert_eq!("data[1]", p.validate().unwrap_err().path);
+        let p = from_json(r#"{ "id": "${req.id + 1}", "data": [], "cycle": 10 }"#);
+        assert_eq!("id", p.validate().unwrap_err().path);
+    }
+}
@@ -1,64 +1,384 @@
-use std::collections::HashMap;
-
 use serde_derive::*;
 
-use crate::can::CANMessage;
-use crate::utils;
+use crate::can::{CANMessage, FrameKind, MAX_CLASSIC_LENGTH, MAX_FD_LENGTH};
+use crate::dbc::{Dbc, MessageDefinition, Signal};
+use crate::error::ValidationError;
+use crate::literal::{self, Number, Pattern, MAX_BYTE};
+
+// The part of a message that a comparison predicate looks at, which is written as "id", as
+// { "byte": 2 }, or as { "start": 2, "length": 2, "endian": "little" } for a multi-byte field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Field {
+    Byte {
+        byte: usize,
+    },
+    Bytes {
+        start: usize,
+        length: usize,
+        #[serde(default)]
+        endian: Endian,
+    },
+    Id(IdField),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IdField {
+    #[serde(rename = "id")]  Id,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum Endian {
+    #[default]
+    #[serde(rename = "big")]     Big,
+    #[serde(rename = "little")]  Little,
+}
+
+const MAX_FIELD_LENGTH: usize = 8;
+
+impl Field {
+    // returns None when the field is not contained in the message
+    pub fn value_in(&self, message: &CANMessage) -> Option<u64> {
+        match *self {
+            Field::Id(_) => Some(message.id as u64),
+            Field::Byte { byte } => message.payload().get(byte).map(|b| *b as u64),
+            Field::Bytes { start, length, endian } => {
+                let bytes = message.payload().get(start..start.checked_add(length)?)?;
+                let fold = |acc: u64, b: &u8| (acc << 8) | *b as u64;
+                match endian {
+                    Endian::Big => Some(bytes.iter().fold(0, fold)),
+                    Endian::Little => Some(bytes.iter().rev().fold(0, fold)),
+                }
+            }
+        }
+    }
+
+    fn validate(&self, kind: &FrameKind) -> Result<u64, ValidationError> {
+        match *self {
+            Field::Id(_) => Ok(literal::max_id(kind.format)),
+            Field::Byte { byte } => {
+                if byte >= MAX_FD_LENGTH {
+                    return Err(ValidationError::new("field.byte", format!("byte {} is beyond the end of a frame", byte)));
+                }
+                Ok(MAX_BYTE)
+            }
+            Field::Bytes { start, length, .. } => {
+                if length == 0 || length > MAX_FIELD_LENGTH {
+                    return Err(ValidationError::new("field.length", format!("length of field must be between 1 and {}", MAX_FIELD_LENGTH)));
+                }
+                if start.checked_add(length).is_none_or(|end| end > MAX_FD_LENGTH) {
+                    return Err(ValidationError::new("field.start", "field extends beyond the end of a frame".to_string()));
+                }
+                Ok(u64::MAX >> (64 - 8 * length))
+            }
+        }
+    }
+}
+
+
+// Compares the physical value of a signal defined in the DBC file of the imposter. The definition
+// of the signal is looked up when the imposter is loaded; all comparisons must hold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignalPredicate {
+    message: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eq: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gt: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lt: Option<f64>,
+    #[serde(skip)]
+    definition: Option<(MessageDefinition, Signal)>,
+}
+
+impl SignalPredicate {
+    fn eval(&self, message: &CANMessage) -> bool {
+        let (definition, signal) = match &self.definition {
+            Some(d) if d.0.matches_id(message) => d,
+            _ => return false
+        };
+        match definition.decode(signal, message.payload()) {
+            // physical values are only precise to one step of the raw value
+            Some(value) => self.eq.is_none_or(|eq| (value - eq).abs() < signal.factor.abs() / 2.0)
+                && self.gt.is_none_or(|gt| value > gt)
+                && self.lt.is_none_or(|lt| value < lt),
+            None => false
+        }
+    }
+}
+
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Predicate {
     #[serde(rename = "eq")]
-    Equals(HashMap<String, String>),
+    Equals {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<Pattern>,
+        #[serde(flatten)]
+        kind: FrameKind,
+    },
     #[serde(rename = "msg")]
-    Message { id: String, data: Vec<String> },
+    Message {
+        id: Pattern,
+        data: Vec<Pattern>,
+        // when strict, frames that are shorter than the pattern never match, even if the
+        // missing bytes are matched by an asterisk
+        #[serde(skip_serializing_if = "Option::is_none")]
+        strict: Option<bool>,
+        #[serde(flatten)]
+        kind: FrameKind,
+    },
+    #[serde(rename = "length")]
+    Length {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        exact: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        min: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        max: Option<usize>,
+        #[serde(flatten)]
+        kind: FrameKind,
+    },
+    // matches when the bits of the id selected by the mask are equal, like an acceptance filter
+    #[serde(rename = "mask")]
+    Mask {
+        id: Number,
+        mask: Number,
+        #[serde(flatten)]
+        kind: FrameKind,
+    },
+    #[serde(rename = "gt")]
+    GreaterThan {
+        field: Field,
+        value: Number,
+        #[serde(flatten)]
+        kind: FrameKind,
+    },
+    #[serde(rename = "lt")]
+    LessThan {
+        field: Field,
+        value: Number,
+        #[serde(flatten)]
+        kind: FrameKind,
+    },
+    // min and max are inclusive
+    #[serde(rename = "between")]
+    Between {
+        field: Field,
+        min: Number,
+        max: Number,
+        #[serde(flatten)]
+        kind: FrameKind,
+    },
+    #[serde(rename = "signal")]
+    Signal(SignalPredicate),
+    #[serde(rename = "and")]
+    And(Vec<Predicate>),
+    #[serde(rename = "or")]
+    Or(Vec<Predicate>),
+    #[serde(rename = "not")]
+    Not(Box<Predicate>),
 }
 
 impl Predicate {
 
     pub fn eval(&self, message: &CANMessage) -> bool {
         match self {
-            Predicate::Equals(args) => {
-                return Predicate::equals(message, args);
+            Predicate::Equals { id, kind } => {
+                Predicate::equals(message, id, kind)
+            }
+            Predicate::Message { id, data, strict, kind } => {
+                if strict == &Some(true) && (message.length as usize) < data.len() {
+                    return false;
+                }
+                kind.matches(message) && Predicate::matches_template(message, id, data)
+            }
+            Predicate::Length { exact, min, max, kind } => {
+                let length = message.length as usize;
+                kind.matches(message)
+                    && exact.is_none_or(|l| length == l)
+                    && min.is_none_or(|l| length >= l)
+                    && max.is_none_or(|l| length <= l)
+            }
+            Predicate::Mask { id, mask, kind } => {
+                kind.matches(message) && (message.id as u64) & mask.value == id.value & mask.value
+            }
+            Predicate::GreaterThan { field, value, kind } => {
+                kind.matches(message) && field.value_in(message).is_some_and(|v| v > value.value)
+            }
+            Predicate::LessThan { field, value, kind } => {
+                kind.matches(message) && field.value_in(message).is_some_and(|v| v < value.value)
             }
-            Predicate::Message { id, data } => {
-                return Predicate::matches_template(message, id, data);
+            Predicate::Between { field, min, max, kind } => {
+                kind.matches(message) && field.value_in(message).is_some_and(|v| v >= min.value && v <= max.value)
+            }
+            Predicate::Signal(signal) => {
+                signal.eval(message)
+            }
+            Predicate::And(predicates) => {
+                predicates.iter().all(|p| p.eval(message))
+            }
+            Predicate::Or(predicates) => {
+                predicates.iter().any(|p| p.eval(message))
+            }
+            Predicate::Not(predicate) => {
+                !predicate.eval(message)
             }
         }
     }
 
-    pub fn equals(message: &CANMessage, args: &HashMap<String, String>) -> bool {
-        if let Some(id) = args.get("id") {
-            return Predicate::matches_value(id, message.id);
-        } else {
-            panic!("invalid args for eq predicate; found {:?}", args);
+    // an eq predicate without id and frame kind is rejected by validation and never matches
+    pub fn equals(message: &CANMessage, id: &Option<Pattern>, kind: &FrameKind) -> bool {
+        if id.is_none() && kind.is_empty() {
+            return false;
         }
+        if let Some(id) = id {
+            if !id.matches(message.id as u64) {
+                return false;
+            }
+        }
+        kind.matches(message)
     }
 
-    pub fn matches_template(message: &CANMessage, id: &String, data: &Vec<String>) -> bool {
-        if Predicate::matches_value(id, message.id) == false {
+    pub fn matches_template(message: &CANMessage, id: &Pattern, data: &[Pattern]) -> bool {
+        if !id.matches(message.id as u64) {
             return false;
         }
-        for i in 0..data.len() {
-            if Predicate::matches_value(&data[i], message.data[i] as u64) == false {
-                return false;
+        // bytes beyond the length of the frame only match an asterisk, whatever the padding is
+        let payload = message.payload();
+        data.iter().enumerate().all(|(i, pattern)| match payload.get(i) {
+            Some(value) => pattern.matches(*value as u64),
+            None => *pattern == Pattern::Any,
+        })
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match self {
+            Predicate::Equals { id, kind } => {
+                if id.is_none() && kind.is_empty() {
+                    return Err(ValidationError::new("eq", "eq predicate needs an id or a frame kind".to_string()));
+                }
+                if let Some(id) = id {
+                    id.check_max("eq.id", literal::max_id(kind.format), "an id")?;
+                }
+            }
+            Predicate::Message { id, data, kind, .. } => {
+                id.check_max("msg.id", literal::max_id(kind.format), "an id")?;
+                let max_length = if kind.fd == Some(false) { MAX_CLASSIC_LENGTH } else { MAX_FD_LENGTH };
+                if data.len() > max_length {
+                    let path = format!("msg.data[{}]", max_length);
+                    return Err(ValidationError::new(&path, format!("msg predicate has {} data bytes; frames cannot have more than {}", data.len(), max_length)));
+                }
+                for (i, pattern) in data.iter().enumerate() {
+                    pattern.check_max(&format!("msg.data[{}]", i), MAX_BYTE, "a data byte")?;
+                }
+            }
+            Predicate::Length { exact, min, max, kind } => {
+                if exact.is_none() && min.is_none() && max.is_none() {
+                    return Err(ValidationError::new("length", "length predicate needs exact, min, or max".to_string()));
+                }
+                if exact.is_some() && (min.is_some() || max.is_some()) {
+                    return Err(ValidationError::new("length.exact", "exact length cannot be combined with min or max".to_string()));
+                }
+                let max_length = if kind.fd == Some(false) { MAX_CLASSIC_LENGTH } else { MAX_FD_LENGTH };
+                for (name, value) in &[("exact", exact), ("min", min), ("max", max)] {
+                    if let Some(l) = value {
+                        if *l > max_length {
+                            let path = format!("length.{}", name);
+                            return Err(ValidationError::new(&path, format!("length {} is out of range; frames cannot have more than {} data bytes", l, max_length)));
+                        }
+                    }
+                }
+                if let (Some(min), Some(max)) = (min, max) {
+                    if min > max {
+                        return Err(ValidationError::new("length", format!("min {} is larger than max {}", min, max)));
+                    }
+                }
+            }
+            Predicate::Mask { id, mask, kind } => {
+                id.check_max("mask.id", literal::max_id(kind.format), "an id")?;
+                mask.check_max("mask.mask", literal::max_id(kind.format), "an id")?;
+            }
+            Predicate::GreaterThan { field, value, kind } => {
+                let max = field.validate(kind).map_err(|e| e.within("gt"))?;
+                value.check_max("gt.value", max, "the field")?;
+            }
+            Predicate::LessThan { field, value, kind } => {
+                let max = field.validate(kind).map_err(|e| e.within("lt"))?;
+                value.check_max("lt.value", max, "the field")?;
+            }
+            Predicate::Between { field, min, max, kind } => {
+                let field_max = field.validate(kind).map_err(|e| e.within("between"))?;
+                min.check_max("between.min", field_max, "the field")?;
+                max.check_max("between.max", field_max, "the field")?;
+                if min.value > max.value {
+                    return Err(ValidationError::new("between", format!("min {} is larger than max {}", min, max)));
+                }
+            }
+            Predicate::Signal(signal) => {
+                if signal.eq.is_none() && signal.gt.is_none() && signal.lt.is_none() {
+                    return Err(ValidationError::new("signal", "signal predicate needs eq, gt, or lt".to_string()));
+                }
+            }
+            Predicate::And(predicates) => {
+                Predicate::validate_all("and", predicates)?;
+            }
+            Predicate::Or(predicates) => {
+                Predicate::validate_all("or", predicates)?;
             }
+            Predicate::Not(predicate) => {
+                predicate.validate().map_err(|e| e.within("not"))?;
+            }
+        }
+        Ok(())
+    }
+
+    // looks up the definitions of the signals used in signal predicates
+    pub fn resolve_signals(&mut self, dbc: Option<&Dbc>) -> Result<(), ValidationError> {
+        match self {
+            Predicate::Signal(signal) => {
+                let dbc = dbc.ok_or_else(|| ValidationError::new("signal", "signal predicates need a DBC file; set dbc on the imposter".to_string()))?;
+                let (definition, s) = dbc.find_signal(&signal.message, &signal.name)
+                    .map_err(|e| ValidationError::new("signal", e))?;
+                signal.definition = Some((definition.clone(), s.clone()));
+            }
+            Predicate::And(predicates) => {
+                Predicate::resolve_all("and", predicates, dbc)?;
+            }
+            Predicate::Or(predicates) => {
+                Predicate::resolve_all("or", predicates, dbc)?;
+            }
+            Predicate::Not(predicate) => {
+                predicate.resolve_signals(dbc).map_err(|e| e.within("not"))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn resolve_all(name: &str, predicates: &mut [Predicate], dbc: Option<&Dbc>) -> Result<(), ValidationError> {
+        for (i, p) in predicates.iter_mut().enumerate() {
+            p.resolve_signals(dbc).map_err(|e| e.within(&format!("{}[{}]", name, i)))?;
         }
-        true
+        Ok(())
     }
 
-    fn matches_value(pattern: &str, value: u64) -> bool {
-        if pattern == "*" {
-            return true;
-        } else {
-            return value == utils::num_from_string_u64(pattern);
+    fn validate_all(name: &str, predicates: &[Predicate]) -> Result<(), ValidationError> {
+        if predicates.is_empty() {
+            return Err(ValidationError::new(name, format!("{} predicate needs at least one predicate", name)));
+        }
+        for (i, p) in predicates.iter().enumerate() {
+            p.validate().map_err(|e| e.within(&format!("{}[{}]", name, i)))?;
         }
+        Ok(())
     }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::can::CANMessage;
+    use crate::can::{CANMessage, FrameFormat};
     use crate::utils;
 
     use super::*;
@@ -70,67 +390,264 @@ mod tests {
     }
 
 
-    #[test]
-    fn pattern_matches_value_when_decimal_number_is_equal() {
-        assert_eq!(true, Predicate::matches_value("256", 0x0100));
-    }
-
-    #[test]
-    fn pattern_does_not_match_value_when_decimal_number_is_not_equal() {
-        assert_eq!(false, Predicate::matches_value("257", 0x0100));
-    }
-
-    #[test]
-    fn pattern_matches_value_when_hexadecimal_number_is_equal() {
-        assert_eq!(true, Predicate::matches_value("0x0101", 0x0101));
-    }
-
-    #[test]
-    fn pattern_matches_value_when_pattern_is_asterisk() {
-        assert_eq!(true, Predicate::matches_value("*", 0x0101));
-    }
-
-
     #[test]
     fn matches_if_id_is_equal() {
         let p = from_json(r#"{ "eq": { "id": "0x0101" } }"#);
-        let message = CANMessage::with_content(0x0101, 0, &[]);
+        let message = CANMessage::with_content(0x0101, &[]);
         assert_eq!(true, p.eval(&message));
     }
 
     #[test]
     fn does_not_match_if_id_is_not_equal() {
         let p = from_json(r#"{ "eq": { "id": "0x0101" } }"#);
-        let message = CANMessage::with_content(0x0102, 0, &[]);
+        let message = CANMessage::with_content(0x0102, &[]);
         assert_eq!(false, p.eval(&message));
     }
 
+    #[test]
+    fn rejects_and_does_not_match_eq_without_id_or_frame_kind() {
+        let p = from_json(r#"{ "eq": {} }"#);
+        assert!(p.validate().is_err());
+        assert!(!p.eval(&CANMessage::new()));
+    }
+
     #[test]
     fn matches_when_id_and_literal_data_match() {
         let p = from_json(r#"{ "msg": { "id": "0x0101", "data": ["0x01"] } }"#);
-        let message = CANMessage::with_content(0x0101, 0, &[0x01]);
+        let message = CANMessage::with_content(0x0101, &[0x01]);
         assert_eq!(true, p.eval(&message));
     }
 
     #[test]
     fn matches_when_id_and_data_with_asterisk_match() {
         let p = from_json(r#"{ "msg": { "id": "0x0101", "data": ["*", "0x02"] } }"#);
-        let message = CANMessage::with_content(0x0101, 0, &[0x01, 0x02]);
+        let message = CANMessage::with_content(0x0101, &[0x01, 0x02]);
         assert_eq!(true, p.eval(&message));
     }
 
     #[test]
     fn does_not_match_when_id_matches_but_data_does_not() {
         let p = from_json(r#"{ "msg": { "id": "0x0101", "data": ["0x02"] } }"#);
-        let message = CANMessage::with_content(0x101, 0, &[0x01]);
+        let message = CANMessage::with_content(0x101, &[0x01]);
         assert_eq!(false, p.eval(&message));
     }
 
     #[test]
     fn does_not_match_when_data_matches_but_id_does_not() {
         let p = from_json(r#"{ "msg": { "id": "0x0102", "data": ["*"] } }"#);
-        let message = CANMessage::with_content(0x0101, 0, &[0x01]);
+        let message = CANMessage::with_content(0x0101, &[0x01]);
+        assert_eq!(false, p.eval(&message));
+    }
+
+    #[test]
+    fn matches_fd_frames_with_long_data_pattern() {
+        let p = from_json(r#"{ "msg": { "id": "0x0101", "data": ["*", "*", "*", "*", "*", "*", "*", "*", "0x09"], "fd": true } }"#);
+        let message = CANMessage::with_fd_content(0x0101, &[0, 0, 0, 0, 0, 0, 0, 0, 0x09]);
+        assert_eq!(true, p.eval(&message));
+    }
+
+    #[test]
+    fn rejects_data_pattern_with_value_larger_than_byte() {
+        let p = from_json(r#"{ "msg": { "id": "0x0101", "data": ["*", "0x100"] } }"#);
+        assert_eq!("msg.data[1]", p.validate().unwrap_err().path);
+    }
+
+    #[test]
+    fn rejects_standard_id_with_more_than_eleven_bits() {
+        let p = from_json(r#"{ "eq": { "id": "0x800", "format": "standard" } }"#);
+        assert!(p.validate().is_err());
+        let p = from_json(r#"{ "eq": { "id": "0x800" } }"#);
+        assert!(p.validate().is_ok());
+    }
+
+    #[test]
+    fn rejects_data_pattern_longer_than_frame() {
+        let p = from_json(r#"{ "msg": { "id": "0x0101", "data": ["*", "*", "*", "*", "*", "*", "*", "*", "0x09"], "fd": false } }"#);
+        assert!(p.validate().is_err());
+    }
+
+    #[test]
+    fn matches_if_id_and_format_are_equal() {
+        let p = from_json(r#"{ "eq": { "id": "0x18DAF110", "format": "extended" } }"#);
+        let mut message = CANMessage::with_content(0x18DAF110, &[]);
         assert_eq!(false, p.eval(&message));
+        message.format = FrameFormat::Extended;
+        assert_eq!(true, p.eval(&message));
+    }
+
+    #[test]
+    fn matches_remote_frames_with_eq_predicate() {
+        let p = from_json(r#"{ "eq": { "rtr": true } }"#);
+        let mut message = CANMessage::with_content(0x0101, &[]);
+        assert_eq!(false, p.eval(&message));
+        message.rtr = true;
+        assert_eq!(true, p.eval(&message));
+    }
+
+    #[test]
+    fn does_not_match_when_template_matches_but_kind_does_not() {
+        let p = from_json(r#"{ "msg": { "id": "0x0101", "data": ["*"], "rtr": false } }"#);
+        let mut message = CANMessage::with_content(0x0101, &[0x01]);
+        message.rtr = true;
+        assert_eq!(false, p.eval(&message));
+    }
+
+    #[test]
+    fn matches_when_any_predicate_of_or_matches() {
+        let p = from_json(r#"{ "or": [ { "eq": { "id": "0x7E0" } }, { "eq": { "id": "0x7DF" } } ] }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x7DF, &[])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x7E1, &[])));
+    }
+
+    #[test]
+    fn matches_only_when_all_predicates_of_and_match() {
+        let p = from_json(r#"{ "and": [ { "eq": { "id": "0x100" } }, { "msg": { "id": "*", "data": [ "0x01" ] } } ] }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x100, &[0x01])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0x02])));
+    }
+
+    #[test]
+    fn matches_when_nested_predicate_of_not_does_not_match() {
+        let p = from_json(r#"{ "not": { "or": [ { "eq": { "id": "0x100" } }, { "eq": { "rtr": true } } ] } }"#);
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[])));
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x101, &[])));
+    }
+
+    #[test]
+    fn serializes_nested_predicates_as_they_were_defined() {
+        let json = r#"{"not":{"and":[{"eq":{"id":"0x100"}},{"msg":{"id":"*","data":["0x01","*"]}}]}}"#;
+        let p = from_json(json);
+        assert_eq!(json, serde_json::to_string(&p).unwrap());
+    }
+
+    #[test]
+    fn reports_path_of_invalid_nested_predicate() {
+        let p = from_json(r#"{ "or": [ { "eq": { "id": "0x100" } }, { "not": { "and": [] } } ] }"#);
+        assert_eq!("or[1].not.and", p.validate().unwrap_err().path);
+    }
+
+    #[test]
+    fn matches_when_id_bits_selected_by_mask_are_equal() {
+        let p = from_json(r#"{ "mask": { "id": "0x600", "mask": "0x780" } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x600, &[])));
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x67F, &[])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x680, &[])));
     }
 
+    #[test]
+    fn matches_data_bytes_with_bit_patterns() {
+        let p = from_json(r#"{ "msg": { "id": "0x100", "data": [ "*", "0bxxxx_1xxx", { "value": "0x01", "mask": "0x0F" } ] } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x100, &[0x00, 0x08, 0xF1])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0x00, 0xF7, 0xF1])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0x00, 0x08, 0xF2])));
+    }
+
+    #[test]
+    fn matches_when_id_is_between_min_and_max() {
+        let p = from_json(r#"{ "between": { "field": "id", "min": "0x600", "max": "0x67F" } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x600, &[])));
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x67F, &[])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x680, &[])));
+    }
+
+    #[test]
+    fn matches_when_byte_is_greater_than_value() {
+        let p = from_json(r#"{ "gt": { "field": { "byte": 1 }, "value": "0x80" } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x100, &[0x00, 0x81])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0x00, 0x80])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0xFF])));
+    }
+
+    #[test]
+    fn compares_multi_byte_fields_with_given_endianness() {
+        let p = from_json(r#"{ "lt": { "field": { "start": 1, "length": 2 }, "value": "0x0200" } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x100, &[0x00, 0x01, 0xFF])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0x00, 0x02, 0x00])));
+        let p = from_json(r#"{ "lt": { "field": { "start": 1, "length": 2, "endian": "little" }, "value": "0x0200" } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x100, &[0x00, 0xFF, 0x01])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0x00, 0x00, 0x02])));
+    }
+
+    #[test]
+    fn rejects_comparison_value_that_does_not_fit_field() {
+        let p = from_json(r#"{ "gt": { "field": { "byte": 0 }, "value": "0x100" } }"#);
+        assert_eq!("gt.value", p.validate().unwrap_err().path);
+        let p = from_json(r#"{ "between": { "field": { "start": 0, "length": 9 }, "min": "0", "max": "1" } }"#);
+        assert_eq!("between.field.length", p.validate().unwrap_err().path);
+        let p = from_json(r#"{ "gt": { "field": { "start": 18446744073709551615, "length": 2 }, "value": "0" } }"#);
+        assert_eq!("gt.field.start", p.validate().unwrap_err().path);
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x100, &[0x01, 0x02])));
+    }
+
+    #[test]
+    fn does_not_match_zero_padding_beyond_length_of_frame() {
+        let p = from_json(r#"{ "msg": { "id": "0x0100", "data": ["0x01", "0x00", "0x00"] } }"#);
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x0100, &[0x01])));
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x0100, &[0x01, 0x00, 0x00])));
+    }
+
+    #[test]
+    fn matches_missing_bytes_with_asterisk_unless_strict() {
+        let p = from_json(r#"{ "msg": { "id": "0x0100", "data": ["0x01", "*"] } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x0100, &[0x01])));
+        let p = from_json(r#"{ "msg": { "id": "0x0100", "data": ["0x01", "*"], "strict": true } }"#);
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x0100, &[0x01])));
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x0100, &[0x01, 0x02, 0x03])));
+    }
+
+    #[test]
+    fn matches_exact_and_range_of_lengths() {
+        let p = from_json(r#"{ "length": { "exact": 2 } }"#);
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x0100, &[0x01, 0x02])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x0100, &[0x01])));
+        let p = from_json(r#"{ "length": { "min": 2, "max": 4 } }"#);
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x0100, &[0x01])));
+        assert_eq!(true, p.eval(&CANMessage::with_content(0x0100, &[0x01, 0x02, 0x03, 0x04])));
+        assert_eq!(false, p.eval(&CANMessage::with_content(0x0100, &[0x01, 0x02, 0x03, 0x04, 0x05])));
+    }
+
+    #[test]
+    fn rejects_invalid_length_predicates() {
+        assert_eq!("length", from_json(r#"{ "length": {} }"#).validate().unwrap_err().path);
+        assert_eq!("length.exact", from_json(r#"{ "length": { "exact": 2, "min": 1 } }"#).validate().unwrap_err().path);
+        assert_eq!("length.max", from_json(r#"{ "length": { "max": 9, "fd": false } }"#).validate().unwrap_err().path);
+        assert_eq!("length", from_json(r#"{ "length": { "min": 4, "max": 2 } }"#).validate().unwrap_err().path);
+    }
+
+    const DBC: &str = r#"
+BO_ 2364540158 EEC1: 8 ECU
+ SG_ EngineSpeed : 24|16@1+ (0.125,0) [0|8031.875] "rpm" Tester
+"#;
+
+    fn resolved(s: &str) -> Predicate {
+        let mut p = from_json(s);
+        p.resolve_signals(Some(&Dbc::parse(DBC).unwrap())).unwrap();
+        p
+    }
+
+    #[test]
+    fn matches_when_physical_value_of_signal_is_in_range() {
+        let p = resolved(r#"{ "signal": { "message": "EEC1", "name": "EngineSpeed", "gt": 3000, "lt": 4000 } }"#);
+        let mut message = CANMessage::with_content(0x0CF004FE, &[0x00, 0x00, 0x00, 0x60, 0x6D, 0x00, 0x00, 0x00]);
+        assert_eq!(false, p.eval(&message));
+        message.format = FrameFormat::Extended;
+        assert_eq!(true, p.eval(&message));
+        message.data[4] = 0x80;
+        assert_eq!(false, p.eval(&message));
+    }
+
+    #[test]
+    fn matches_when_physical_value_of_signal_is_equal() {
+        let p = resolved(r#"{ "not": { "signal": { "message": "EEC1", "name": "EngineSpeed", "eq": 3000 } } }"#);
+        let mut message = CANMessage::with_content(0x0CF004FE, &[0x00, 0x00, 0x00, 0xC0, 0x5D, 0x00, 0x00, 0x00]);
+        message.format = FrameFormat::Extended;
+        assert_eq!(false, p.eval(&message));
+    }
+
+    #[test]
+    fn fails_to_resolve_unknown_signal_or_missing_dbc() {
+        let mut p = from_json(r#"{ "or": [ { "eq": { "id": "0x100" } }, { "signal": { "message": "EEC1", "name": "RPM", "gt": 3000 } } ] }"#);
+        assert_eq!("or[1].signal", p.resolve_signals(Some(&Dbc::parse(DBC).unwrap())).unwrap_err().path);
+        assert!(p.resolve_signals(None).is_err());
+    }
 }
@@ -1,41 +1,319 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand_distr::{Distribution, Normal};
 use serde_derive::*;
 
-use crate::can::CANMessage;
-use crate::utils;
+use crate::can::{CANMessage, FrameFormat, FrameKind, MAX_CLASSIC_LENGTH, MAX_FD_LENGTH};
+use crate::dbc::{Dbc, MessageDefinition, Multiplex, Signal};
+use crate::error::ValidationError;
+use crate::expression::ValueTemplate;
+use crate::fault::Fault;
+use crate::generator::{Checksum, Counter};
+use crate::literal::{self, MAX_BYTE, MAX_EXTENDED_ID, MAX_STANDARD_ID};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResponseTemplate {
-    id: String,
-    data: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<ValueTemplate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<ValueTemplate>>,
+    // instead of id and data a response can name a message in the DBC file of the imposter and
+    // give physical values for its signals
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signals: Option<BTreeMap<String, f64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    counter: Option<Counter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<Checksum>,
+    #[serde(flatten)]
+    kind: FrameKind,
     #[serde(rename = "_behaviors")]
     pub behaviors: Option<Vec<Behavior>>,
+    #[serde(skip)]
+    definition: Option<MessageDefinition>,
+}
+
+// a message generated by a stub or a periodic entry, ready to be sent
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub message: CANMessage,
+    pub stub: Option<usize>,  // index of the stub that generated the response
+    pub periodic: Option<usize>,  // index of the periodic entry that generated the message
+    pub request: Option<u64>,  // sequence number of the message the response is for
+    pub delay: Duration,  // how long to wait before sending the message
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Behavior {
-    #[serde(rename = "wait")]    Wait(u64),
+    #[serde(rename = "wait")]    Wait(WaitTime),
     #[serde(rename = "repeat")]  Repeat(usize),
     #[serde(rename = "drop")]    Drop(bool),
     #[serde(rename = "concat")]  Concat(bool),
+    #[serde(rename = "fault")]   Fault(Box<Fault>),
+}
+
+// A wait is either a number of milliseconds or a random time, which is drawn from a uniform
+// distribution between min and max, or from a normal distribution with mean and stddev
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WaitTime {
+    Fixed(u64),
+    Random(Box<RandomWait>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RandomWait {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mean: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stddev: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit: Option<TimeUnit>,
+    // with a seed the sequence of wait times is the same every time the stub is loaded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip)]
+    rng: Option<StdRng>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TimeUnit {
+    #[serde(rename = "ms")]  Milliseconds,
+    #[serde(rename = "us")]  Microseconds,
+}
+
+impl WaitTime {
+    pub fn sample(&mut self) -> Duration {
+        match self {
+            WaitTime::Fixed(millis) => Duration::from_millis(*millis),
+            WaitTime::Random(w) => w.sample(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        match self {
+            WaitTime::Fixed(_) => Ok(()),
+            WaitTime::Random(w) => w.validate(),
+        }
+    }
+}
+
+impl RandomWait {
+    // negative times drawn from a normal distribution result in no wait
+    fn sample(&mut self) -> Duration {
+        let seed = self.seed;
+        let rng = self.rng.get_or_insert_with(|| match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        });
+        let time = match (self.value, self.min, self.max, self.mean, self.stddev) {
+            (Some(value), _, _, _, _) => value,
+            (_, Some(min), Some(max), _, _) => rng.gen_range(min..=max),
+            (_, _, _, Some(mean), Some(stddev)) => Normal::new(mean, stddev).map_or(mean, |n| n.sample(rng)),
+            _ => 0.0
+        };
+        let micros = match self.unit {
+            Some(TimeUnit::Microseconds) => time,
+            _ => time * 1000.0,
+        };
+        Duration::from_micros(micros.max(0.0).round() as u64)
+    }
+
+    fn validate(&self) -> Result<(), ValidationError> {
+        let kinds = [self.value.is_some(), self.min.is_some() || self.max.is_some(), self.mean.is_some() || self.stddev.is_some()];
+        if kinds.iter().filter(|k| **k).count() != 1 {
+            return Err(ValidationError::new("", "wait needs either value, min and max, or mean and stddev".to_string()));
+        }
+        let values = [("value", self.value), ("min", self.min), ("max", self.max), ("mean", self.mean), ("stddev", self.stddev)];
+        for (name, value) in &values {
+            if value.is_some_and(|v| !v.is_finite() || v < 0.0) {
+                return Err(ValidationError::new(name, format!("{} must not be negative", name)));
+            }
+        }
+        match (self.min, self.max, self.mean, self.stddev) {
+            (Some(min), Some(max), _, _) if min > max => Err(ValidationError::new("min", format!("min {} is larger than max {}", min, max))),
+            (Some(_), None, _, _) => Err(ValidationError::new("max", "uniform wait needs min and max".to_string())),
+            (None, Some(_), _, _) => Err(ValidationError::new("min", "uniform wait needs min and max".to_string())),
+            (_, _, Some(_), None) => Err(ValidationError::new("stddev", "normal wait needs mean and stddev".to_string())),
+            (_, _, None, Some(_)) => Err(ValidationError::new("mean", "normal wait needs mean and stddev".to_string())),
+            _ => Ok(())
+        }
+    }
 }
 
 
 impl ResponseTemplate {
-    pub fn generate_response(&self, _message: &CANMessage) -> CANMessage {
+    // Values of expressions are cut to the bits that fit into the id or data byte. The count is
+    // the number of responses the stub has generated before, which is used for the counter.
+    pub fn generate_response(&self, message: &CANMessage, count: u64) -> CANMessage {
         let mut response = CANMessage::new();
-        response.id = utils::num_from_string_u64(&self.id);
-        response.length = self.data.len() as u8;
-        for i in 0..(self.data.len()) {
-            response.data[i] = utils::num_from_string_u64(&self.data[i]) as u8;
+        self.kind.apply_to(&mut response);
+        if let Some(definition) = &self.definition {
+            response.id = definition.id;
+            response.format = if definition.extended { FrameFormat::Extended } else { FrameFormat::Standard };
+            response.set_data(&definition.encode(&self.signal_values(definition)));
+        } else {
+            // an id computed from the request has the format of the request unless one is given
+            if self.kind.format.is_none() && self.id.as_ref().is_some_and(|id| id.uses_request()) {
+                response.format = message.format;
+            }
+            let max_id = literal::max_id(Some(response.format));
+            response.id = self.id.as_ref().map_or(0, |id| (id.value(message) & max_id) as u32);
+            let data: Vec<u8> = self.data.iter().flatten().map(|d| d.value(message) as u8).collect();
+            response.set_data(&data);
+        }
+        let length = response.length as usize;
+        if let Some(counter) = &self.counter {
+            counter.apply_to(&mut response.data[..length], count);
+        }
+        if let Some(checksum) = &self.checksum {
+            checksum.apply_to(&mut response.data[..length]);
         }
         response
     }
+
+    // the sum of the wait behaviors; random waits are drawn again every time
+    pub fn wait_time(&mut self) -> Duration {
+        self.behaviors.iter_mut().flatten()
+            .map(|b| match b {
+                Behavior::Wait(w) => w.sample(),
+                _ => Duration::from_millis(0),
+            })
+            .sum()
+    }
+
+    // returns the messages to send for the response, and the number of faults injected
+    pub fn inject_faults(&mut self, message: CANMessage) -> (Vec<CANMessage>, u64) {
+        let mut messages = vec![message];
+        let mut count = 0;
+        for b in self.behaviors.iter_mut().flatten() {
+            if let Behavior::Fault(fault) = b {
+                if fault.apply_to(&mut messages) {
+                    count += 1;
+                }
+            }
+        }
+        (messages, count)
+    }
+
+    // for messages that are not sent in response to a request, e.g. periodic messages
+    pub fn validate_without_request(&self) -> Result<(), ValidationError> {
+        if self.id.as_ref().is_some_and(|id| id.uses_request()) {
+            return Err(ValidationError::new("id", "expression cannot refer to req without a request".to_string()));
+        }
+        if let Some(i) = self.data.iter().flatten().position(|b| b.uses_request()) {
+            return Err(ValidationError::new(&format!("data[{}]", i), "expression cannot refer to req without a request".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for (i, b) in self.behaviors.iter().flatten().enumerate() {
+            match b {
+                Behavior::Wait(w) => w.validate().map_err(|e| e.within(&format!("_behaviors[{}].wait", i)))?,
+                Behavior::Fault(f) => f.validate().map_err(|e| e.within(&format!("_behaviors[{}].fault", i)))?,
+                _ => {}
+            }
+        }
+        if let Some(counter) = &self.counter {
+            counter.validate().map_err(|e| e.within("counter"))?;
+        }
+        if let Some(checksum) = &self.checksum {
+            checksum.validate().map_err(|e| e.within("checksum"))?;
+        }
+        let (id, data) = match (&self.id, &self.data, &self.message) {
+            (Some(id), Some(data), None) => (id, data),
+            (None, None, Some(_)) => return Ok(()),
+            (_, _, Some(_)) => return Err(ValidationError::new("message", "response with a DBC message cannot have id or data".to_string())),
+            (None, _, None) => return Err(ValidationError::new("id", "response needs an id and data, or a DBC message".to_string())),
+            (_, None, None) => return Err(ValidationError::new("data", "response needs an id and data, or a DBC message".to_string())),
+        };
+        if self.signals.is_some() {
+            return Err(ValidationError::new("signals", "signals can only be given for a DBC message".to_string()));
+        }
+        let max_length = if self.kind.is_fd() { MAX_FD_LENGTH } else { MAX_CLASSIC_LENGTH };
+        if data.len() > max_length {
+            let frame_type = if self.kind.is_fd() { "FD" } else { "classic" };
+            let path = format!("data[{}]", max_length);
+            return Err(ValidationError::new(&path, format!("response has {} data bytes; {} frames cannot have more than {}", data.len(), frame_type, max_length)));
+        }
+        for (i, d) in data.iter().enumerate() {
+            d.check_max(&format!("data[{}]", i), MAX_BYTE, "a data byte")?;
+        }
+        self.check_fields_within(data.len())?;
+        match self.kind.format {
+            Some(FrameFormat::Extended) => id.check_max("id", MAX_EXTENDED_ID, "an extended id"),
+            _ => id.check_max("id", MAX_STANDARD_ID, "a standard id"),
+        }
+    }
+
+    // looks up the DBC message of the response and checks that the signal values can be encoded
+    pub fn resolve_signals(&mut self, dbc: Option<&Dbc>) -> Result<(), ValidationError> {
+        let name = match &self.message {
+            Some(name) => name,
+            None => return Ok(())
+        };
+        let dbc = dbc.ok_or_else(|| ValidationError::new("message", "responses with a message need a DBC file; set dbc on the imposter".to_string()))?;
+        let definition = dbc.message(name).ok_or_else(|| ValidationError::new("message", format!("DBC file has no message {}", name)))?;
+        if definition.length > MAX_CLASSIC_LENGTH && !self.kind.is_fd() {
+            return Err(ValidationError::new("message", format!("message {} has {} data bytes; set fd for CAN FD frames", name, definition.length)));
+        }
+        let mut selected = None;
+        for (signal_name, value) in self.signals.iter().flatten() {
+            let path = format!("signals.{}", signal_name);
+            let signal = definition.signal(signal_name).ok_or_else(|| ValidationError::new(&path, format!("message {} has no signal {}", name, signal_name)))?;
+            let raw = signal.to_raw(*value).map_err(|e| ValidationError::new(&path, e))?;
+            let multiplex = match signal.multiplex {
+                Multiplex::Multiplexor => Some(raw),
+                Multiplex::Multiplexed(m) => Some(m),
+                Multiplex::None => None,
+            };
+            if let Some(m) = multiplex {
+                if selected.is_some_and(|s| s != m) {
+                    return Err(ValidationError::new(&path, format!("signal {} is not selected by the multiplexor value of the other signals", signal_name)));
+                }
+                selected = Some(m);
+            }
+        }
+        self.check_fields_within(definition.length)?;
+        self.definition = Some(definition.clone());
+        Ok(())
+    }
+
+    // bytes beyond the data of the response would be skipped silently when the response is generated
+    fn check_fields_within(&self, length: usize) -> Result<(), ValidationError> {
+        let fields = [("counter.byte", self.counter.as_ref().map(|c| c.field.byte)), ("checksum.byte", self.checksum.as_ref().map(|c| c.field.byte))];
+        for (path, byte) in &fields {
+            if byte.is_some_and(|b| b >= length) {
+                return Err(ValidationError::new(path, format!("byte is beyond the {} data bytes of the response", length)));
+            }
+        }
+        Ok(())
+    }
+
+    fn signal_values<'a>(&self, definition: &'a MessageDefinition) -> Vec<(&'a Signal, f64)> {
+        self.signals.iter().flatten()
+            .filter_map(|(name, value)| definition.signal(name).map(|s| (s, *value)))
+            .collect()
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::can::CANMessage;
+    use crate::can::{CANMessage, FrameFormat};
+    use crate::generator::crc8;
     use crate::utils::from_json;
 
     use super::*;
@@ -43,13 +321,108 @@ mod tests {
     #[test]
     fn creates_response_with_hex_id_and_data_from_template() {
         let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": ["0x017", "0x03"] }"#);
-        let response = t.generate_response(&CANMessage::new());
+        let response = t.generate_response(&CANMessage::new(), 0);
         assert_eq!(0x0102, response.id);
         assert_eq!(2, response.length);
         assert_eq!(0x17, response.data[0]);
         assert_eq!(0x03, response.data[1]);
     }
 
+    #[test]
+    fn creates_extended_remote_frame_when_template_specifies_kind() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x18DAF110", "data": [], "format": "extended", "rtr": true }"#);
+        let response = t.generate_response(&CANMessage::new(), 0);
+        assert_eq!(0x18DAF110, response.id);
+        assert_eq!(FrameFormat::Extended, response.format);
+        assert_eq!(true, response.rtr);
+        assert_eq!(false, response.error);
+    }
+
+    #[test]
+    fn creates_fd_frame_padded_to_valid_length() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": ["0x01", "0x02", "0x03", "0x04", "0x05",
+                                                 "0x06", "0x07", "0x08", "0x09"], "fd": true, "brs": true }"#);
+        let response = t.generate_response(&CANMessage::new(), 0);
+        assert_eq!(true, response.fd);
+        assert_eq!(true, response.brs);
+        assert_eq!(12, response.length);
+        assert_eq!(0x09, response.data[8]);
+    }
+
+    #[test]
+    fn rejects_classic_frame_with_more_than_eight_bytes() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": ["0x01", "0x02", "0x03", "0x04", "0x05",
+                                                 "0x06", "0x07", "0x08", "0x09"] }"#);
+        assert!(t.validate().is_err());
+    }
+
+    #[test]
+    fn rejects_data_byte_larger_than_0xff() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": ["0x01", "256"] }"#);
+        assert_eq!("data[1]", t.validate().unwrap_err().path);
+    }
+
+    #[test]
+    fn rejects_id_that_does_not_fit_format() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0800", "data": [] }"#);
+        assert_eq!("id", t.validate().unwrap_err().path);
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0800", "data": [], "format": "extended" }"#);
+        assert!(t.validate().is_ok());
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x20000000", "data": [], "format": "extended" }"#);
+        assert!(t.validate().is_err());
+    }
+
+    #[test]
+    fn creates_response_from_expressions_on_request() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "${req.id + 8}", "data": ["0x62", "${req.data[1]}", "${req.data[2]}"] }"#);
+        let response = t.generate_response(&CANMessage::with_content(0x7E0, &[0x22, 0xF1, 0x90]), 0);
+        assert_eq!(0x7E8, response.id);
+        assert_eq!([0x62, 0xF1, 0x90], response.data[..3]);
+    }
+
+    #[test]
+    fn cuts_values_of_expressions_to_size_of_field() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "${req.id + 1}", "data": ["${req.data[0] + 1}"] }"#);
+        let response = t.generate_response(&CANMessage::with_content(0x7FF, &[0xFF]), 0);
+        assert_eq!(0x000, response.id);
+        assert_eq!(0x00, response.data[0]);
+    }
+
+    #[test]
+    fn uses_format_of_request_for_id_computed_from_request() {
+        let mut request = CANMessage::with_content(0x18DA10F1, &[]);
+        request.format = FrameFormat::Extended;
+        let t: ResponseTemplate = from_json(r#"{ "id": "${req.id ^ 0xFFFF}", "data": [] }"#);
+        let response = t.generate_response(&request, 0);
+        assert_eq!(FrameFormat::Extended, response.format);
+        assert_eq!(0x18DAEF0E, response.id);
+        let t: ResponseTemplate = from_json(r#"{ "id": "${req.id}", "data": [], "format": "standard" }"#);
+        assert_eq!(0x0F1, t.generate_response(&request, 0).id);
+    }
+
+    #[test]
+    fn sets_counter_before_computing_checksum() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": ["0x00", "0x00", "0x00", "0x00"],
+                                                 "counter": { "byte": 1, "length": 4 }, "checksum": { "type": "crc8", "byte": 0 } }"#);
+        t.validate().unwrap();
+        let response = t.generate_response(&CANMessage::new(), 17);
+        assert_eq!(0x01, response.data[1]);
+        assert_eq!(crc8(&[0x01, 0x00, 0x00], 0x1D), response.data[0]);
+    }
+
+    #[test]
+    fn rejects_counter_beyond_data_of_response() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": ["0x00"], "counter": { "byte": 1 } }"#);
+        assert_eq!("counter.byte", t.validate().unwrap_err().path);
+    }
+
+    #[test]
+    fn rejects_checksum_beyond_data_of_dbc_message() {
+        let mut t: ResponseTemplate = from_json(r#"{ "message": "VehicleData", "checksum": { "type": "xor", "byte": 4 } }"#);
+        t.validate().unwrap();
+        assert_eq!("checksum.byte", t.resolve_signals(Some(&Dbc::parse(DBC).unwrap())).unwrap_err().path);
+    }
+
     #[test]
     fn parses_behavior_from_template() {
         let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": ["0x017" ],
@@ -57,9 +430,78 @@ mod tests {
         assert!(t.behaviors.is_some());
         if let Some(b) = t.behaviors {
             match &b[0] {
-                Behavior::Wait(arg) => { assert_eq!(500, *arg); }
+                Behavior::Wait(WaitTime::Fixed(arg)) => { assert_eq!(500, *arg); }
                 _ => panic!("expected to find wait behavior")
             }
         }
     }
+
+    fn wait_time(json: &str) -> WaitTime {
+        from_json(json)
+    }
+
+    #[test]
+    fn draws_uniform_wait_times_in_microseconds() {
+        let mut w = wait_time(r#"{ "min": 200, "max": 300, "unit": "us" }"#);
+        for _ in 0..100 {
+            let d = w.sample();
+            assert!(d >= Duration::from_micros(200) && d <= Duration::from_micros(300));
+        }
+    }
+
+    #[test]
+    fn draws_same_wait_times_with_same_seed() {
+        let json = r#"{ "mean": 50, "stddev": 10, "seed": 42 }"#;
+        let (mut w1, mut w2) = (wait_time(json), wait_time(json));
+        let times1: Vec<Duration> = (0..5).map(|_| w1.sample()).collect();
+        let times2: Vec<Duration> = (0..5).map(|_| w2.sample()).collect();
+        assert_eq!(times1, times2);
+        assert!(times1.iter().any(|t| *t != times1[0]));
+    }
+
+    #[test]
+    fn rejects_wait_with_incomplete_or_mixed_distribution() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": [], "_behaviors": [ { "concat": false }, { "wait": { "min": 10 } } ] }"#);
+        assert_eq!("_behaviors[1].wait.max", t.validate().unwrap_err().path);
+        assert!(wait_time(r#"{ "value": 10, "mean": 20, "stddev": 1 }"#).validate().is_err());
+        assert!(wait_time(r#"{ "min": 20, "max": 10 }"#).validate().is_err());
+    }
+
+    const DBC: &str = r#"
+BO_ 1280 VehicleData: 4 ECU
+ SG_ Speed_kph : 0|16@1+ (0.5,0) [0|32767.5] "km/h" Tester
+ SG_ Page M : 16|2@1+ (1,0) [0|3] "" Tester
+ SG_ Odometer m1 : 24|8@1+ (1,0) [0|255] "" Tester
+ SG_ Range m2 : 24|8@1+ (1,0) [0|255] "" Tester
+"#;
+
+    #[test]
+    fn encodes_signal_values_of_dbc_message() {
+        let mut t: ResponseTemplate = from_json(r#"{ "message": "VehicleData", "signals": { "Speed_kph": 88.5, "Odometer": 7 } }"#);
+        t.validate().unwrap();
+        t.resolve_signals(Some(&Dbc::parse(DBC).unwrap())).unwrap();
+        let response = t.generate_response(&CANMessage::new(), 0);
+        assert_eq!(0x500, response.id);
+        assert_eq!(4, response.length);
+        assert_eq!([0xB1, 0x00, 0x01, 0x07], response.data[..4]);
+    }
+
+    #[test]
+    fn rejects_signals_that_are_unknown_or_do_not_fit() {
+        let dbc = Dbc::parse(DBC).unwrap();
+        let mut t: ResponseTemplate = from_json(r#"{ "message": "VehicleData", "signals": { "Speed": 88.5 } }"#);
+        assert_eq!("signals.Speed", t.resolve_signals(Some(&dbc)).unwrap_err().path);
+        let mut t: ResponseTemplate = from_json(r#"{ "message": "VehicleData", "signals": { "Speed_kph": -1 } }"#);
+        assert_eq!("signals.Speed_kph", t.resolve_signals(Some(&dbc)).unwrap_err().path);
+        let mut t: ResponseTemplate = from_json(r#"{ "message": "VehicleData", "signals": { "Odometer": 1, "Range": 2 } }"#);
+        assert_eq!("signals.Range", t.resolve_signals(Some(&dbc)).unwrap_err().path);
+    }
+
+    #[test]
+    fn rejects_response_with_both_data_and_message() {
+        let t: ResponseTemplate = from_json(r#"{ "id": "0x0102", "data": [], "message": "VehicleData" }"#);
+        assert_eq!("message", t.validate().unwrap_err().path);
+        let t: ResponseTemplate = from_json(r#"{ "data": [] }"#);
+        assert_eq!("id", t.validate().unwrap_err().path);
+    }
 }
@@ -1,9 +1,10 @@
-use std::thread;
 use std::time::Duration;
 
 use serde_derive::*;
 
 use crate::can::CANMessage;
+use crate::dbc::Dbc;
+use crate::error::ValidationError;
 use crate::predicate::Predicate;
 use crate::response::{Behavior, ResponseTemplate};
 
@@ -15,6 +16,13 @@ pub struct Stub {
     response_idx: usize,
     #[serde(skip)]
     response_repeats: usize,
+    // number of responses generated, for the counters in responses; responses dropped by a behavior
+    // or fault are not counted, but responses that the adaptor fails to send are
+    #[serde(skip)]
+    response_count: u64,
+    // faults injected since the imposter last took the count
+    #[serde(skip)]
+    injected_faults: u64,
 }
 
 impl Stub {
@@ -23,7 +31,9 @@ impl Stub {
         self.predicates.iter().find(|p| p.eval(message) == false).is_none()
     }
 
-    pub fn generate_responses(&mut self, message: &CANMessage) -> Vec<CANMessage> {
+    // Returns the responses with the time to wait before sending them. The waits add up, so that
+    // concatenated responses are sent in order.
+    pub fn generate_responses(&mut self, message: &CANMessage) -> Vec<(CANMessage, Duration)> {
         if self.responses.len() == 0 {
             panic!("cannot generate response; no response template defined on stub");
         }
@@ -31,20 +41,34 @@ impl Stub {
         let prev_idx = self.response_idx;
         let mut responses = Vec::new();
         let mut generate_response = true;
+        let mut delay = Duration::from_millis(0);
 
         while generate_response {
-            responses.push(self.get_template().generate_response(message));
+            let response = self.get_template().generate_response(message, self.response_count);
+            delay += self.responses[self.response_idx].wait_time();
             generate_response = false;
-            if let Some(behaviors) = self.get_template().behaviors.clone() {
-                for b in behaviors {
-                    match b {
-                        Behavior::Wait(arg) => { thread::sleep(Duration::from_millis(arg)); }
-                        Behavior::Repeat(arg) => { self.update_response_repeats(arg); }
-                        Behavior::Drop(arg) => { if arg { responses.pop(); } }
-                        Behavior::Concat(arg) => { generate_response = arg }
-                    }
+            let mut drop_response = false;
+            let mut repeats = Vec::new();
+            for b in self.get_template().behaviors.iter().flatten() {
+                match *b {
+                    Behavior::Wait(_) => {}
+                    Behavior::Repeat(arg) => { repeats.push(arg); }
+                    Behavior::Drop(arg) => { drop_response = arg; }
+                    Behavior::Concat(arg) => { generate_response = arg }
+                    Behavior::Fault(_) => {}
                 }
             }
+            for arg in repeats {
+                self.update_response_repeats(arg);
+            }
+            if !drop_response {
+                let (messages, faults) = self.responses[self.response_idx].inject_faults(response);
+                self.injected_faults += faults;
+                if !messages.is_empty() {
+                    self.response_count = self.response_count.wrapping_add(1);
+                }
+                responses.extend(messages.into_iter().map(|m| (m, delay)));
+            }
             self.inc_response_idx();
         }
 
@@ -55,6 +79,33 @@ impl Stub {
         responses
     }
 
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for (i, p) in self.predicates.iter().enumerate() {
+            p.validate().map_err(|e| e.within(&format!("predicates[{}]", i)))?;
+        }
+        if self.responses.is_empty() {
+            return Err(ValidationError::new("responses", "stub has no responses".to_string()));
+        }
+        for (i, r) in self.responses.iter().enumerate() {
+            r.validate().map_err(|e| e.within(&format!("responses[{}]", i)))?;
+        }
+        Ok(())
+    }
+
+    pub fn resolve_signals(&mut self, dbc: Option<&Dbc>) -> Result<(), ValidationError> {
+        for (i, p) in self.predicates.iter_mut().enumerate() {
+            p.resolve_signals(dbc).map_err(|e| e.within(&format!("predicates[{}]", i)))?;
+        }
+        for (i, r) in self.responses.iter_mut().enumerate() {
+            r.resolve_signals(dbc).map_err(|e| e.within(&format!("responses[{}]", i)))?;
+        }
+        Ok(())
+    }
+
+    pub fn take_injected_faults(&mut self) -> u64 {
+        std::mem::replace(&mut self.injected_faults, 0)
+    }
+
     fn get_template(&self) -> &ResponseTemplate {
         &self.responses[self.response_idx]
     }
@@ -95,27 +146,73 @@ mod tests {
                       ]
                    }"#);
 
-        let response1 = stub.generate_responses(&CANMessage::new())[0];
+        let response1 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x01, response1.id);
-        let response2 = stub.generate_responses(&CANMessage::new())[0];
+        let response2 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x02, response2.id);
-        let response3 = stub.generate_responses(&CANMessage::new())[0];
+        let response3 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x01, response3.id);
     }
 
     #[test]
-    fn wait_behavior_waits_before_responding() {
+    fn increments_counter_with_every_response() {
+        let mut stub = from_json(r#"{
+                     "predicates": [],
+                     "responses": [
+                        { "id": "0x01", "data": [ "0x00" ], "counter": { "byte": 0, "length": 2 } }
+                      ]
+                   }"#);
+
+        let counters: Vec<u8> = (0..5).map(|_| stub.generate_responses(&CANMessage::new())[0].0.data[0]).collect();
+        assert_eq!(vec![0, 1, 2, 3, 0], counters);
+    }
+
+    #[test]
+    fn does_not_increment_counter_for_dropped_responses() {
         let mut stub = from_json(r#"{
                      "predicates": [],
                      "responses": [
-                        { "id": "0x01", "data": [ "0x17" ], "_behaviors": [ { "wait": 50 } ] }
+                        { "id": "0x01", "data": [ "0x00" ], "counter": { "byte": 0 } },
+                        { "id": "0x01", "data": [ "0x00" ], "counter": { "byte": 0 }, "_behaviors": [ { "drop": true } ] },
+                        { "id": "0x01", "data": [ "0x00" ], "counter": { "byte": 0 },
+                          "_behaviors": [ { "fault": { "type": "drop", "rate": 1 } } ] },
+                        { "id": "0x01", "data": [ "0x00" ], "counter": { "byte": 0 } }
+                      ]
+                   }"#);
+
+        let counters: Vec<u8> = (0..4).flat_map(|_| stub.generate_responses(&CANMessage::new())).map(|(m, _)| m.data[0]).collect();
+        assert_eq!(vec![0, 1], counters);
+    }
+
+    #[test]
+    fn wait_behavior_delays_response_without_blocking() {
+        let mut stub = from_json(r#"{
+                     "predicates": [],
+                     "responses": [
+                        { "id": "0x01", "data": [ "0x17" ], "_behaviors": [ { "wait": 500 } ] }
                       ]
                    }"#);
 
         let start = Instant::now();
-        stub.generate_responses(&CANMessage::new());
-        let end = Instant::now();
-        assert!(end.duration_since(start).subsec_millis() >= 50);
+        let responses = stub.generate_responses(&CANMessage::new());
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(Duration::from_millis(500), responses[0].1);
+    }
+
+    #[test]
+    fn waits_of_concatenated_responses_add_up() {
+        let mut stub = from_json(r#"{
+                     "predicates": [],
+                     "responses": [
+                        { "id": "0x01", "data": [], "_behaviors": [ { "wait": 100 }, { "concat": true } ] },
+                        { "id": "0x02", "data": [], "_behaviors": [ { "wait": 50 } ] }
+                      ]
+                   }"#);
+
+        let responses = stub.generate_responses(&CANMessage::new());
+        assert_eq!(2, responses.len());
+        assert_eq!(Duration::from_millis(100), responses[0].1);
+        assert_eq!(Duration::from_millis(100 + 50), responses[1].1);
     }
 
     #[test]
@@ -128,13 +225,13 @@ mod tests {
                       ]
                    }"#);
 
-        let response1 = stub.generate_responses(&CANMessage::new())[0];
+        let response1 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x01, response1.id);
-        let response2 = stub.generate_responses(&CANMessage::new())[0];
+        let response2 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x01, response2.id);
-        let response3 = stub.generate_responses(&CANMessage::new())[0];
+        let response3 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x02, response3.id);
-        let response4 = stub.generate_responses(&CANMessage::new())[0];
+        let response4 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x01, response4.id);
     }
 
@@ -150,7 +247,7 @@ mod tests {
 
         let responses = stub.generate_responses(&CANMessage::new());
         assert_eq!(0, responses.len());
-        let response2 = stub.generate_responses(&CANMessage::new())[0];
+        let response2 = stub.generate_responses(&CANMessage::new())[0].0;
         assert_eq!(0x02, response2.id);
     }
 
@@ -168,12 +265,12 @@ mod tests {
 
         let responses = stub.generate_responses(&CANMessage::new());
         assert_eq!(3, responses.len());
-        assert_eq!(0x01, responses[0].id);
-        assert_eq!(0x02, responses[1].id);
-        assert_eq!(0x03, responses[2].id);
+        assert_eq!(0x01, responses[0].0.id);
+        assert_eq!(0x02, responses[1].0.id);
+        assert_eq!(0x03, responses[2].0.id);
         let responses = stub.generate_responses(&CANMessage::new());
         assert_eq!(1, responses.len());
-        assert_eq!(0x04, responses[0].id);
+        assert_eq!(0x04, responses[0].0.id);
     }
 
     #[test]
@@ -197,4 +294,22 @@ mod tests {
         let responses3 = stub.generate_responses(&CANMessage::new());
         assert_eq!(1, responses3.len());
     }
+
+    #[test]
+    fn fault_behaviors_change_messages_and_are_counted() {
+        let mut stub = from_json(r#"{
+                     "predicates": [],
+                     "responses": [
+                        { "id": "0x01", "data": [], "_behaviors": [ { "fault": { "type": "duplicate", "rate": 1 } } ] },
+                        { "id": "0x02", "data": [], "_behaviors": [ { "fault": { "type": "drop", "rate": 0 } } ] }
+                      ]
+                   }"#);
+
+        let responses = stub.generate_responses(&CANMessage::new());
+        assert_eq!(2, responses.len());
+        assert_eq!(0x01, responses[1].0.id);
+        assert_eq!(1, stub.generate_responses(&CANMessage::new()).len());
+        assert_eq!(1, stub.take_injected_faults());
+        assert_eq!(0, stub.take_injected_faults());
+    }
 }
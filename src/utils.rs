@@ -1,19 +1,37 @@
+use std::sync::OnceLock;
+use std::time::Instant;
+
 use serde::Deserialize;
+use serde::de::DeserializeOwned;
 use serde_json;
+use serde_json::error::Category;
 
-pub fn num_from_string_u64(string: &str) -> u64 {
-    if string.starts_with("0x") {
-        if let Ok(n) = u64::from_str_radix(&string[2..], 16) {
-            return n;
-        }
-    } else {
-        if let Ok(n) = string.parse::<u64>() {
-            return n;
-        }
-    }
-    panic!("failed to parse number; found {}", string);
-}
+use crate::error::ImposterError;
+
+static CLOCK_START: OnceLock<Instant> = OnceLock::new();
 
 pub fn from_json<'a, T>(s: &'a str) -> T where T: Deserialize<'a> {
     serde_json::from_str(s).expect("Failed to parse JSON")
 }
+
+// unlike from_json this does not panic, and when the document does not match the expected schema
+// the error contains the path of the element that caused the problem
+pub fn parse_json<T>(s: &str) -> Result<T, ImposterError> where T: DeserializeOwned {
+    let mut deserializer = serde_json::Deserializer::from_str(s);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        let error = e.into_inner();
+        match error.classify() {
+            Category::Data => ImposterError::Schema { path: if path == "." { String::new() } else { path }, error },
+            _ => ImposterError::Syntax(error)
+        }
+    })?;
+    deserializer.end().map_err(ImposterError::Syntax)?;
+    Ok(value)
+}
+
+// microseconds on a monotonic clock that starts when this function is first called
+pub fn monotonic_micros() -> u64 {
+    let start = CLOCK_START.get_or_init(Instant::now);
+    start.elapsed().as_micros() as u64
+}
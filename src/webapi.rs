@@ -1,5 +1,9 @@
 use crate::controller::ImposterList;
+use crate::error::{ImposterError, ValidationError};
 use crate::imposter::Imposter;
+use crate::periodic::Periodic;
+use crate::stub::Stub;
+use crate::utils;
 use futures::{future, Future, Stream};
 use gotham::handler::HandlerFuture;
 use gotham::helpers::http::response::{create_empty_response, create_response};
@@ -11,18 +15,48 @@ use gotham::state::{FromState, State};
 use gotham_derive::*;
 use hyper::{Body, Response, StatusCode, Uri};
 use serde_derive::*;
-use serde_json::{Value, Error};
 
 #[derive(Serialize, Clone)]
 struct ImposterListWrapper {
     imposters: Vec<Imposter>
 }
 
+#[derive(Serialize)]
+struct ErrorListWrapper {
+    errors: Vec<ErrorDescription>
+}
+
+#[derive(Serialize)]
+struct ErrorDescription {
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct StubWrapper {
+    index: Option<usize>,
+    stub: Stub,
+}
+
+#[derive(Deserialize)]
+struct PeriodicWrapper {
+    index: Option<usize>,
+    periodic: Periodic,
+}
+
 #[derive(Deserialize, StateData, StaticResponseExtender)]
 struct IdParam {
     id: u32,
 }
 
+#[derive(Deserialize, StateData, StaticResponseExtender)]
+struct StubParam {
+    id: u32,
+    index: usize,
+}
+
 
 pub fn run(addr: String, imposters: ImposterList) {
     println!("Listening for requests at http://{}", addr);
@@ -37,8 +71,16 @@ pub fn router(imposters: ImposterList) -> Router {
         route.get("/ping").to(get_ping);
         route.get("/imposters").to(get_all_imposters);
         route.get("/imposters/:id").with_path_extractor::<IdParam>().to(get_imposter);
+        route.get("/imposters/:id/status").with_path_extractor::<IdParam>().to(get_port_status);
         route.post("/imposters").to(post_imposter);
         route.delete("/imposters/:id").with_path_extractor::<IdParam>().to(delete_imposter);
+        route.delete("/imposters/:id/savedMessages").with_path_extractor::<IdParam>().to(delete_saved_messages);
+        route.post("/imposters/:id/stubs").with_path_extractor::<IdParam>().to(post_stub);
+        route.put("/imposters/:id/stubs/:index").with_path_extractor::<StubParam>().to(put_stub);
+        route.delete("/imposters/:id/stubs/:index").with_path_extractor::<StubParam>().to(delete_stub);
+        route.post("/imposters/:id/periodic").with_path_extractor::<IdParam>().to(post_periodic);
+        route.put("/imposters/:id/periodic/:index").with_path_extractor::<StubParam>().to(put_periodic);
+        route.delete("/imposters/:id/periodic/:index").with_path_extractor::<StubParam>().to(delete_periodic);
     })
 }
 
@@ -70,20 +112,33 @@ fn get_imposter(mut state: State) -> (State, Response<Body>) {
     (state, response)
 }
 
+fn get_port_status(mut state: State) -> (State, Response<Body>) {
+    let p = IdParam::take_from(&mut state);
+    let response;
+    if let Some(status) = ImposterList::borrow_from(&state).port_status(p.id) {
+        let mut response_body = serde_json::to_string_pretty(&status).unwrap();
+        response_body.push('\n');
+        response = create_response(&state, StatusCode::OK, mime::APPLICATION_JSON, response_body);
+    } else {
+        response = create_empty_response(&state, StatusCode::NOT_FOUND);
+    }
+    (state, response)
+}
+
 fn post_imposter(mut state: State) -> Box<HandlerFuture> {
     let f = Body::take_from(&mut state).concat2().then(|full_body| {
         // TODO: consider adding explicit error handling for body and UTF-8 problems
         let body_content = String::from_utf8(full_body.unwrap().to_vec()).unwrap();
-        let response = match serde_json::from_str::<Value>(&body_content) {
-            Ok(value) => {
-                println!("Webapi: imposters << {}", value);
-                let imposter = Imposter::from_json(&body_content);
+        println!("Webapi: imposters << {}", body_content);
+        let dbc_dir = ImposterList::borrow_from(&state).dbc_dir().to_path_buf();
+        let response = match Imposter::from_json_with_dbc_dir(&body_content, &dbc_dir) {
+            Ok(imposter) => {
                 let id = imposter.id;
                 let did_create = ImposterList::borrow_from(&state).upsert(imposter);
                 create_post_ok_response(&state, id, did_create)
             }
             Err(error) => {
-                create_json_parse_error_response(&state, &error)
+                create_error_response(&state, &error)
             }
         };
         future::ok((state, response))
@@ -101,10 +156,188 @@ fn delete_imposter(mut state: State) -> (State, Response<Body>) {
     (state, response)
 }
 
+fn delete_saved_messages(mut state: State) -> (State, Response<Body>) {
+    let p = IdParam::take_from(&mut state);
+    let response = match ImposterList::borrow_from(&state).update_by_id(p.id, |i| i.clear_messages()) {
+        Some(()) => create_empty_response(&state, StatusCode::NO_CONTENT),
+        None => create_empty_response(&state, StatusCode::NOT_FOUND)
+    };
+    (state, response)
+}
+
+// adding, replacing and removing single stubs leaves the state of the other stubs intact, which
+// is not the case when the whole imposter is posted again
+
+fn post_stub(mut state: State) -> Box<HandlerFuture> {
+    let p = IdParam::take_from(&mut state);
+    let f = Body::take_from(&mut state).concat2().then(move |full_body| {
+        let body_content = String::from_utf8(full_body.unwrap().to_vec()).unwrap();
+        println!("Webapi: imposters/{}/stubs << {}", p.id, body_content);
+        let response = match parse_stub_wrapper(&body_content) {
+            Ok(mut wrapper) => {
+                let list = ImposterList::borrow_from(&state);
+                let result = list.update_by_id(p.id, |i| -> Result<_, ValidationError> {
+                    i.resolve_signals(&mut wrapper.stub).map_err(|e| e.within("stub"))?;
+                    Ok(i.insert_stub(wrapper.index, wrapper.stub))
+                });
+                match result {
+                    Some(Ok(Some(index))) => create_created_response(&state, "Created stub\n", index),
+                    Some(Ok(None)) => {
+                        let error = ValidationError::new("index", "index is out of range".to_string());
+                        create_error_response(&state, &error.into())
+                    }
+                    Some(Err(error)) => create_error_response(&state, &error.into()),
+                    None => create_empty_response(&state, StatusCode::NOT_FOUND)
+                }
+            }
+            Err(error) => {
+                create_error_response(&state, &error)
+            }
+        };
+        future::ok((state, response))
+    });
+    Box::new(f)
+}
+
+fn put_stub(mut state: State) -> Box<HandlerFuture> {
+    let p = StubParam::take_from(&mut state);
+    let f = Body::take_from(&mut state).concat2().then(move |full_body| {
+        let body_content = String::from_utf8(full_body.unwrap().to_vec()).unwrap();
+        println!("Webapi: imposters/{}/stubs/{} << {}", p.id, p.index, body_content);
+        // the errors have the same paths as when the stub is posted in a wrapper
+        let response = match parse_stub(&body_content).map_err(|e| e.within("stub")) {
+            Ok(mut stub) => {
+                let result = ImposterList::borrow_from(&state).update_by_id(p.id, |i| -> Result<_, ValidationError> {
+                    i.resolve_signals(&mut stub).map_err(|e| e.within("stub"))?;
+                    Ok(i.replace_stub(p.index, stub))
+                });
+                match result {
+                    Some(Ok(true)) => create_response(&state, StatusCode::OK, mime::TEXT_PLAIN, "Updated stub\n"),
+                    Some(Err(error)) => create_error_response(&state, &error.into()),
+                    _ => create_empty_response(&state, StatusCode::NOT_FOUND)
+                }
+            }
+            Err(error) => {
+                create_error_response(&state, &error)
+            }
+        };
+        future::ok((state, response))
+    });
+    Box::new(f)
+}
+
+fn delete_stub(mut state: State) -> (State, Response<Body>) {
+    let p = StubParam::take_from(&mut state);
+    let response = match ImposterList::borrow_from(&state).update_by_id(p.id, |i| i.remove_stub(p.index)) {
+        Some(true) => create_empty_response(&state, StatusCode::NO_CONTENT),
+        _ => create_empty_response(&state, StatusCode::NOT_FOUND)
+    };
+    (state, response)
+}
+
+fn post_periodic(mut state: State) -> Box<HandlerFuture> {
+    let p = IdParam::take_from(&mut state);
+    let f = Body::take_from(&mut state).concat2().then(move |full_body| {
+        let body_content = String::from_utf8(full_body.unwrap().to_vec()).unwrap();
+        println!("Webapi: imposters/{}/periodic << {}", p.id, body_content);
+        let response = match parse_periodic_wrapper(&body_content) {
+            Ok(mut wrapper) => {
+                let list = ImposterList::borrow_from(&state);
+                let result = list.update_by_id(p.id, |i| -> Result<_, ValidationError> {
+                    i.resolve_periodic_signals(&mut wrapper.periodic).map_err(|e| e.within("periodic"))?;
+                    Ok(i.insert_periodic(wrapper.index, wrapper.periodic))
+                });
+                match result {
+                    Some(Ok(Some(index))) => create_created_response(&state, "Created periodic message\n", index),
+                    Some(Ok(None)) => {
+                        let error = ValidationError::new("index", "index is out of range".to_string());
+                        create_error_response(&state, &error.into())
+                    }
+                    Some(Err(error)) => create_error_response(&state, &error.into()),
+                    None => create_empty_response(&state, StatusCode::NOT_FOUND)
+                }
+            }
+            Err(error) => {
+                create_error_response(&state, &error)
+            }
+        };
+        future::ok((state, response))
+    });
+    Box::new(f)
+}
 
-fn create_json_parse_error_response(state: &State, error: &Error) -> Response<Body> {
-    let response_body = format!("Error parsing JSON document: {}\n", error);
-    create_response(state, StatusCode::BAD_REQUEST, mime::TEXT_PLAIN, response_body)
+// replacing an entry restarts its schedule, which can also be used to enable or disable it
+fn put_periodic(mut state: State) -> Box<HandlerFuture> {
+    let p = StubParam::take_from(&mut state);
+    let f = Body::take_from(&mut state).concat2().then(move |full_body| {
+        let body_content = String::from_utf8(full_body.unwrap().to_vec()).unwrap();
+        println!("Webapi: imposters/{}/periodic/{} << {}", p.id, p.index, body_content);
+        let response = match parse_periodic(&body_content).map_err(|e| e.within("periodic")) {
+            Ok(mut periodic) => {
+                let result = ImposterList::borrow_from(&state).update_by_id(p.id, |i| -> Result<_, ValidationError> {
+                    i.resolve_periodic_signals(&mut periodic).map_err(|e| e.within("periodic"))?;
+                    Ok(i.replace_periodic(p.index, periodic))
+                });
+                match result {
+                    Some(Ok(true)) => create_response(&state, StatusCode::OK, mime::TEXT_PLAIN, "Updated periodic message\n"),
+                    Some(Err(error)) => create_error_response(&state, &error.into()),
+                    _ => create_empty_response(&state, StatusCode::NOT_FOUND)
+                }
+            }
+            Err(error) => {
+                create_error_response(&state, &error)
+            }
+        };
+        future::ok((state, response))
+    });
+    Box::new(f)
+}
+
+fn delete_periodic(mut state: State) -> (State, Response<Body>) {
+    let p = StubParam::take_from(&mut state);
+    let response = match ImposterList::borrow_from(&state).update_by_id(p.id, |i| i.remove_periodic(p.index)) {
+        Some(true) => create_empty_response(&state, StatusCode::NO_CONTENT),
+        _ => create_empty_response(&state, StatusCode::NOT_FOUND)
+    };
+    (state, response)
+}
+
+
+fn parse_stub(json: &str) -> Result<Stub, ImposterError> {
+    let stub: Stub = utils::parse_json(json)?;
+    stub.validate()?;
+    Ok(stub)
+}
+
+fn parse_stub_wrapper(json: &str) -> Result<StubWrapper, ImposterError> {
+    let wrapper: StubWrapper = utils::parse_json(json)?;
+    wrapper.stub.validate().map_err(|e| e.within("stub"))?;
+    Ok(wrapper)
+}
+
+fn parse_periodic(json: &str) -> Result<Periodic, ImposterError> {
+    let periodic: Periodic = utils::parse_json(json)?;
+    periodic.validate()?;
+    Ok(periodic)
+}
+
+fn parse_periodic_wrapper(json: &str) -> Result<PeriodicWrapper, ImposterError> {
+    let wrapper: PeriodicWrapper = utils::parse_json(json)?;
+    wrapper.periodic.validate().map_err(|e| e.within("periodic"))?;
+    Ok(wrapper)
+}
+
+
+fn create_error_response(state: &State, error: &ImposterError) -> Response<Body> {
+    let code = match error {
+        ImposterError::Syntax(_) => "invalid JSON",
+        _ => "bad data"
+    };
+    let description = ErrorDescription { code, path: error.path().map(String::from), message: error.message() };
+    let wrapper = ErrorListWrapper { errors: vec![description] };
+    let mut response_body = serde_json::to_string_pretty(&wrapper).unwrap();
+    response_body.push('\n');
+    create_response(state, StatusCode::BAD_REQUEST, mime::APPLICATION_JSON, response_body)
 }
 
 fn create_post_ok_response(state: &State, id: u32, created: bool) -> Response<Body> {
@@ -114,7 +347,14 @@ fn create_post_ok_response(state: &State, id: u32, created: bool) -> Response<Bo
         (StatusCode::OK, "Updated imposter\n")
     };
     let mut response = create_response(state, status, mime::TEXT_PLAIN, response_body);
-    let location = format!("{}/{}", Uri::borrow_from(&state), id);
+    let location = format!("{}/{}", Uri::borrow_from(state), id);
+    response.headers_mut().insert("Location", location.parse().unwrap());
+    response
+}
+
+fn create_created_response(state: &State, body: &'static str, index: usize) -> Response<Body> {
+    let mut response = create_response(state, StatusCode::CREATED, mime::TEXT_PLAIN, body);
+    let location = format!("{}/{}", Uri::borrow_from(state), index);
     response.headers_mut().insert("Location", location.parse().unwrap());
     response
 }
@@ -1,42 +1,91 @@
 extern crate candouble;
 
-use candouble::can::{CANMessage, CANAdaptor};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use candouble::can::{CANMessage, CANAdaptor, CANError, ErrorClass, ErrorKind, FrameFormat};
 use candouble::imposter::Imposter;
 use candouble::imposter;
-use candouble::controller::ImposterList;
+use candouble::controller::{ImposterList, PortState, PortStatus};
 
 
+// returns transient errors first, then the incoming message, and then it pretends that the
+// device is gone, which ends the imposter loop
 struct MockAdaptor {
+    transient_errors: u32,
     incoming_message: Option<CANMessage>,
     sent_message: Option<CANMessage>
 }
 
 impl CANAdaptor for MockAdaptor {
-    fn receive(&mut self) -> Result<CANMessage, &'static str> {
+    fn receive(&mut self, _timeout: Duration) -> Result<Option<CANMessage>, CANError> {
+        if self.transient_errors > 0 {
+            self.transient_errors -= 1;
+            return Err(CANError::new(ErrorKind::Overrun, "glitch"));
+        }
         if let Some(message) = self.incoming_message {
             self.incoming_message = None;
-            return Ok(message);
+            return Ok(Some(message));
         }
-        Err("no more messages")
+        Err(CANError::new(ErrorKind::DeviceGone, "no more messages"))
     }
 
-    fn send(&mut self, message: &CANMessage) -> Result<(), &'static str> {
+    fn send(&mut self, message: &CANMessage) -> Result<(), CANError> {
         self.sent_message = Some(message.clone());
         Ok(())
     }
 }
 
+// returns the incoming messages first, then waits, and pretends that the device is gone when
+// the time is up
+struct TimedAdaptor {
+    incoming_messages: Vec<CANMessage>,
+    until: Instant,
+    sent_messages: Vec<(Instant, CANMessage)>,
+}
+
+impl TimedAdaptor {
+    fn new(incoming_messages: Vec<CANMessage>, duration: Duration) -> TimedAdaptor {
+        TimedAdaptor { incoming_messages, until: Instant::now() + duration, sent_messages: Vec::new() }
+    }
+}
+
+impl CANAdaptor for TimedAdaptor {
+    fn receive(&mut self, timeout: Duration) -> Result<Option<CANMessage>, CANError> {
+        if !self.incoming_messages.is_empty() {
+            return Ok(Some(self.incoming_messages.remove(0)));
+        }
+        if Instant::now() >= self.until {
+            return Err(CANError::new(ErrorKind::DeviceGone, "time is up"));
+        }
+        thread::sleep(timeout);
+        Ok(None)
+    }
+
+    fn send(&mut self, message: &CANMessage) -> Result<(), CANError> {
+        self.sent_messages.push((Instant::now(), *message));
+        Ok(())
+    }
+}
+
+fn status() -> Mutex<PortStatus> {
+    Mutex::new(PortStatus::new("mock"))
+}
+
 
 #[test]
 fn it_stub_matches_when_all_predicates_are_true() {
-    let imposter = Imposter::from_file("tests/it_imposter.json");
+    let imposter = Imposter::from_file("tests/it_imposter.json", Path::new(".")).unwrap();
     let list = ImposterList::new();
     list.upsert(imposter);
 
-    let message = CANMessage::with_content(0x0101, 0, &[0xCA, 0xFE]);
-    let mut adaptor = MockAdaptor { incoming_message: Some(message), sent_message: None };
+    let message = CANMessage::with_content(0x0101, &[0xCA, 0xFE]);
+    let mut adaptor = MockAdaptor { transient_errors: 0, incoming_message: Some(message), sent_message: None };
 
-    imposter::run_with_adaptor(123, list, &mut adaptor);
+    imposter::run_with_adaptor(123, list, &mut adaptor, &AtomicBool::new(false), &status()).unwrap_err();
 
     assert_eq!(true, adaptor.sent_message.is_some());
     assert_eq!(0x102, adaptor.sent_message.unwrap().id);
@@ -44,14 +93,109 @@ fn it_stub_matches_when_all_predicates_are_true() {
 
 #[test]
 fn it_stub_does_not_match_when_at_least_one_predicate_is_false() {
-    let imposter = Imposter::from_file("tests/it_imposter.json");
+    let imposter = Imposter::from_file("tests/it_imposter.json", Path::new(".")).unwrap();
     let list = ImposterList::new();
     list.upsert(imposter);
 
-    let message = CANMessage::with_content(0x0101, 0, &[0x00, 0x00]);
-    let mut adaptor = MockAdaptor { incoming_message: Some(message), sent_message: None };
+    let message = CANMessage::with_content(0x0101, &[0x00, 0x00]);
+    let mut adaptor = MockAdaptor { transient_errors: 0, incoming_message: Some(message), sent_message: None };
 
-    imposter::run_with_adaptor(123, list, &mut adaptor);
+    imposter::run_with_adaptor(123, list, &mut adaptor, &AtomicBool::new(false), &status()).unwrap_err();
 
     assert_eq!(true, adaptor.sent_message.is_none());
 }
+
+#[test]
+fn it_stub_matches_signal_defined_in_dbc_file() {
+    let imposter = Imposter::from_json(r#"{
+        "id": 7,
+        "dbc": "tests/it_imposter.dbc",
+        "stubs": [
+            {
+              "predicates": [{ "signal": { "message": "EEC1", "name": "EngineSpeed", "gt": 3000 } }],
+              "responses": [{ "id": "0x0102", "data": [] }]
+            }
+        ]}"#).unwrap();
+    let list = ImposterList::new();
+    list.upsert(imposter);
+
+    // engine speed is 0x6D60 * 0.125 = 3500 rpm
+    let mut message = CANMessage::with_content(0x0CF004FE, &[0x00, 0x00, 0x00, 0x60, 0x6D, 0x00, 0x00, 0x00]);
+    message.format = FrameFormat::Extended;
+    let mut adaptor = MockAdaptor { transient_errors: 0, incoming_message: Some(message), sent_message: None };
+
+    imposter::run_with_adaptor(7, list, &mut adaptor, &AtomicBool::new(false), &status()).unwrap_err();
+
+    assert_eq!(true, adaptor.sent_message.is_some());
+}
+
+#[test]
+fn it_sends_periodic_messages_while_waiting_for_incoming_messages() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "recordMessages": true, "stubs": [],
+                                         "periodic": [{ "id": "0x0301", "data": [], "cycle": 10 }] }"#).unwrap());
+    let mut adaptor = TimedAdaptor::new(vec![], Duration::from_millis(100));
+
+    imposter::run_with_adaptor(1, list.clone(), &mut adaptor, &AtomicBool::new(false), &status()).unwrap_err();
+
+    let count = adaptor.sent_messages.len();
+    assert!((5..=11).contains(&count), "sent {} messages", count);
+    let messages = list.get_by_id(1).unwrap().messages;
+    assert_eq!(Some(0), messages[0].periodic);
+}
+
+#[test]
+fn it_responds_to_other_messages_while_response_waits() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [
+            { "predicates": [{ "eq": { "id": "0x0101" } }], "responses": [{ "id": "0x0201", "data": [], "_behaviors": [{ "wait": 200 }] }] },
+            { "predicates": [{ "eq": { "id": "0x0102" } }], "responses": [{ "id": "0x0202", "data": [] }] }
+        ] }"#).unwrap());
+    let incoming = vec![CANMessage::with_content(0x0101, &[]), CANMessage::with_content(0x0102, &[])];
+    let mut adaptor = TimedAdaptor::new(incoming, Duration::from_millis(300));
+    let start = Instant::now();
+
+    imposter::run_with_adaptor(1, list, &mut adaptor, &AtomicBool::new(false), &status()).unwrap_err();
+
+    let sent: Vec<u32> = adaptor.sent_messages.iter().map(|(_, m)| m.id).collect();
+    assert_eq!(vec![0x0202, 0x0201], sent);
+    assert!(adaptor.sent_messages[0].0 - start < Duration::from_millis(100));
+    assert!(adaptor.sent_messages[1].0 - start >= Duration::from_millis(200));
+}
+
+#[test]
+fn it_records_messages_with_timestamp() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "recordMessages": true, "stubs": [] }"#).unwrap());
+
+    let mut message = CANMessage::with_content(0x0101, &[0xCA, 0xFE]);
+    message.timestamp = Some(1234);
+    let mut adaptor = MockAdaptor { transient_errors: 0, incoming_message: Some(message), sent_message: None };
+    imposter::run_with_adaptor(1, list.clone(), &mut adaptor, &AtomicBool::new(false), &status()).unwrap_err();
+    let mut adaptor = MockAdaptor { transient_errors: 0, incoming_message: Some(CANMessage::with_content(0x0102, &[])), sent_message: None };
+    imposter::run_with_adaptor(1, list.clone(), &mut adaptor, &AtomicBool::new(false), &status()).unwrap_err();
+
+    let messages = list.get_by_id(1).unwrap().messages;
+    assert_eq!(Some(1234), messages[0].message.timestamp);
+    assert_eq!(true, messages[1].message.timestamp.is_some());
+}
+
+#[test]
+fn it_keeps_running_after_transient_errors() {
+    let imposter = Imposter::from_file("tests/it_imposter.json", Path::new(".")).unwrap();
+    let list = ImposterList::new();
+    list.upsert(imposter);
+
+    let message = CANMessage::with_content(0x0101, &[0xCA, 0xFE]);
+    let mut adaptor = MockAdaptor { transient_errors: 2, incoming_message: Some(message), sent_message: None };
+    let status = status();
+
+    let result = imposter::run_with_adaptor(123, list, &mut adaptor, &AtomicBool::new(false), &status);
+
+    assert_eq!(ErrorKind::DeviceGone, result.unwrap_err().kind);
+    assert_eq!(true, adaptor.sent_message.is_some());
+    let status = status.lock().unwrap();
+    assert_eq!(PortState::Running, status.state);
+    assert_eq!(3, status.error_count);
+    assert_eq!(ErrorClass::DeviceGone, status.last_error.as_ref().unwrap().class);
+}
@@ -86,12 +86,74 @@ fn it_returns_400_for_unparseable_json() {
     assert_eq!(0, list.get_all().len());
 }
 
+#[test]
+fn it_returns_400_for_classic_response_with_more_than_eight_bytes() {
+    let doc = r#"{
+                    "id": 1,
+                    "stubs": [
+                        { "predicates": [{ "eq": { "id": "0x01" } }],
+                          "responses": [{ "id": "0x02", "data": [ "0", "1", "2", "3", "4", "5", "6", "7", "8" ] }] }
+                    ]
+                 }"#;
+    let list = ImposterList::new();
+    let client = client(list.clone());
+
+    let response = client.post(url("/imposters"), doc.to_string(), mime::APPLICATION_JSON).perform().unwrap();
+
+    assert_eq!(400, response.status());
+    assert_eq!(0, list.get_all().len());
+    let body = as_json_obj(response);
+    assert_eq!("stubs[0].responses[0].data[8]", body["errors"][0]["path"]);
+}
+
+#[test]
+fn it_returns_400_with_path_for_imposter_that_does_not_match_schema() {
+    let doc = r#"{
+                    "id": 1,
+                    "stubs": [
+                        { "predicates": [{ "eq": { "id": "0x01" } }],
+                          "responses": [{ "id": "0x02", "data": [ "0x01" ] }, { "id": "0x02", "data": "0x01" }] }
+                    ]
+                 }"#;
+    let list = ImposterList::new();
+    let client = client(list.clone());
+
+    let response = client.post(url("/imposters"), doc.to_string(), mime::APPLICATION_JSON).perform().unwrap();
+
+    assert_eq!(400, response.status());
+    let body = as_json_obj(response);
+    assert_eq!("bad data", body["errors"][0]["code"]);
+    assert_eq!("stubs[0].responses[1].data", body["errors"][0]["path"]);
+
+    let response = client.get(&url("/ping")).perform().unwrap();
+    assert_eq!(200, response.status());
+}
+
+#[test]
+fn it_returns_400_with_path_for_invalid_number() {
+    let doc = r#"{
+                    "id": 1,
+                    "stubs": [
+                        { "predicates": [{ "msg": { "id": "0x01", "data": [ "*", "0xZZ" ] } }],
+                          "responses": [{ "id": "0x02", "data": [ "0x01" ] }] }
+                    ]
+                 }"#;
+    let list = ImposterList::new();
+    let client = client(list.clone());
+
+    let response = client.post(url("/imposters"), doc.to_string(), mime::APPLICATION_JSON).perform().unwrap();
+
+    assert_eq!(400, response.status());
+    let body = as_json_obj(response);
+    assert_eq!("stubs[0].predicates[0].msg.data[1]", body["errors"][0]["path"]);
+}
+
 
 #[test]
 fn it_can_get_all_imposters() {
     let list = ImposterList::new();
-    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#));
-    list.upsert(Imposter::from_json(r#"{ "id": 2, "stubs": [ ] }"#));
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#).unwrap());
+    list.upsert(Imposter::from_json(r#"{ "id": 2, "stubs": [ ] }"#).unwrap());
     let client = client(list.clone());
 
     let response = client.get(&url("/imposters")).perform().unwrap();
@@ -104,8 +166,8 @@ fn it_can_get_all_imposters() {
 #[test]
 fn it_can_get_imposter_by_id() {
     let list = ImposterList::new();
-    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#));
-    list.upsert(Imposter::from_json(r#"{ "id": 3, "stubs": [ ] }"#));
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#).unwrap());
+    list.upsert(Imposter::from_json(r#"{ "id": 3, "stubs": [ ] }"#).unwrap());
     let client = client(list.clone());
 
     let response = client.get(&url("/imposters/3")).perform().unwrap();
@@ -118,8 +180,8 @@ fn it_can_get_imposter_by_id() {
 #[test]
 fn it_imposter_contains_received_messages() {
     let list = ImposterList::new();
-    let mut imposter = Imposter::from_json(r#"{ "id": 1, "recordMessages": true, "stubs": [ ] }"#);
-    let message = CANMessage::with_content(0x200, 0, &[]);
+    let mut imposter = Imposter::from_json(r#"{ "id": 1, "recordMessages": true, "stubs": [ ] }"#).unwrap();
+    let message = CANMessage::with_content(0x200, &[]);
     imposter.responses_to_message(&message);
     list.upsert(imposter);
     let client = client(list.clone());
@@ -131,14 +193,45 @@ fn it_imposter_contains_received_messages() {
     assert_eq!(1, messages.as_array().unwrap().len());
     let first_message = messages.get(0).unwrap();
     assert_eq!(0x200, first_message.get("id").unwrap().as_i64().unwrap());
+    assert_eq!("in", first_message.get("direction").unwrap());
+}
+
+
+#[test]
+fn it_can_get_port_status_of_imposter() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#).unwrap());
+    let client = client(list);
+
+    let response = client.get(&url("/imposters/1/status")).perform().unwrap();
+    assert_eq!(200, response.status());
+    let body = as_json_obj(response);
+    assert_eq!("stopped", body["state"]);
+
+    let response = client.get(&url("/imposters/2/status")).perform().unwrap();
+    assert_eq!(404, response.status());
 }
 
+#[test]
+fn it_returns_nested_predicates_as_posted() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [
+                    { "predicates": [{ "not": { "or": [ { "eq": { "id": "0x7E0" } }, { "eq": { "id": "0x7DF" } } ] } }],
+                      "responses": [{ "id": "0x301", "data": [ ] }] }
+                ] }"#).unwrap());
+    let client = client(list);
+
+    let response = client.get(&url("/imposters/1")).perform().unwrap();
+
+    let body = as_json_obj(response);
+    assert_eq!("0x7DF", body["stubs"][0]["predicates"][0]["not"]["or"][1]["eq"]["id"]);
+}
 
 #[test]
 fn it_returns_404_for_non_existing_imposter() {
     let list = ImposterList::new();
-    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#));
-    list.upsert(Imposter::from_json(r#"{ "id": 3, "stubs": [ ] }"#));
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#).unwrap());
+    list.upsert(Imposter::from_json(r#"{ "id": 3, "stubs": [ ] }"#).unwrap());
     let client = client(list.clone());
 
     let response = client.get(&url("/imposters/2")).perform().unwrap();
@@ -149,7 +242,7 @@ fn it_returns_404_for_non_existing_imposter() {
 #[test]
 fn it_can_delete_imposter_by_id() {
     let list = ImposterList::new();
-    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#));
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [ ] }"#).unwrap());
     let client = client(list.clone());
 
     let response = client.delete(&url("/imposters/1")).perform().unwrap();
@@ -158,3 +251,130 @@ fn it_can_delete_imposter_by_id() {
     assert_eq!(0, list.get_all().len());
 }
 
+
+#[test]
+fn it_can_delete_saved_messages() {
+    let list = ImposterList::new();
+    let mut imposter = Imposter::from_json(r#"{ "id": 1, "recordMessages": true, "stubs": [ ] }"#).unwrap();
+    imposter.responses_to_message(&CANMessage::with_content(0x200, &[]));
+    list.upsert(imposter);
+    let client = client(list.clone());
+
+    let response = client.delete(&url("/imposters/1/savedMessages")).perform().unwrap();
+
+    assert_eq!(204, response.status());
+    assert_eq!(0, list.get_by_id(1).unwrap().messages.len());
+}
+
+#[test]
+fn it_can_post_stub_at_index() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [
+                    { "predicates": [{ "eq": { "id": "0x201" } }], "responses": [{ "id": "0x301", "data": [ ] }] }
+                ] }"#).unwrap());
+    let client = client(list.clone());
+    let doc = r#"{ "index": 0, "stub": { "predicates": [{ "eq": { "id": "0x202" } }], "responses": [{ "id": "0x302", "data": [ ] }] } }"#;
+
+    let response = client.post(&url("/imposters/1/stubs"), doc, mime::APPLICATION_JSON).perform().unwrap();
+
+    assert_eq!(201, response.status());
+    assert_eq!("/imposters/1/stubs/0", response.headers().get("Location").unwrap());
+    assert_eq!(2, list.get_by_id(1).unwrap().stubs.len());
+}
+
+#[test]
+fn it_returns_404_when_posting_stub_to_non_existing_imposter() {
+    let client = client(ImposterList::new());
+    let doc = r#"{ "stub": { "predicates": [], "responses": [{ "id": "0x302", "data": [ ] }] } }"#;
+
+    let response = client.post(&url("/imposters/1/stubs"), doc, mime::APPLICATION_JSON).perform().unwrap();
+
+    assert_eq!(404, response.status());
+}
+
+#[test]
+fn it_keeps_state_of_other_stubs_when_replacing_stub() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [
+                    { "predicates": [{ "eq": { "id": "0x201" } }], "responses": [{ "id": "0x301", "data": [ ] }, { "id": "0x302", "data": [ ] }] },
+                    { "predicates": [{ "eq": { "id": "0x202" } }], "responses": [{ "id": "0x303", "data": [ ] }] }
+                ] }"#).unwrap());
+    let message = CANMessage::with_content(0x201, &[]);
+    list.update_by_id(1, |i| i.responses_to_message(&message));
+    let client = client(list.clone());
+    let doc = r#"{ "predicates": [{ "eq": { "id": "0x202" } }], "responses": [{ "id": "0x304", "data": [ ] }] }"#;
+
+    let response = client.put(&url("/imposters/1/stubs/1"), doc, mime::APPLICATION_JSON).perform().unwrap();
+
+    assert_eq!(200, response.status());
+    let responses = list.update_by_id(1, |i| i.responses_to_message(&message)).unwrap();
+    assert_eq!(0x302, responses[0].message.id);
+}
+
+#[test]
+fn it_returns_400_with_same_path_for_invalid_stub_when_posting_and_replacing() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [
+                    { "predicates": [], "responses": [{ "id": "0x301", "data": [ ] }] }
+                ] }"#).unwrap());
+    let client = client(list);
+    let stub = r#"{ "predicates": [], "responses": [{ "id": "0x800", "data": [ ] }] }"#;
+
+    let response = client.post(&url("/imposters/1/stubs"), format!(r#"{{ "stub": {} }}"#, stub), mime::APPLICATION_JSON).perform().unwrap();
+    assert_eq!(400, response.status());
+    assert_eq!("stub.responses[0].id", as_json_obj(response)["errors"][0]["path"]);
+
+    let response = client.put(&url("/imposters/1/stubs/0"), stub, mime::APPLICATION_JSON).perform().unwrap();
+    assert_eq!(400, response.status());
+    assert_eq!("stub.responses[0].id", as_json_obj(response)["errors"][0]["path"]);
+}
+
+#[test]
+fn it_can_delete_stub_by_index() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [
+                    { "predicates": [], "responses": [{ "id": "0x301", "data": [ ] }] }
+                ] }"#).unwrap());
+    let client = client(list.clone());
+
+    let response = client.delete(&url("/imposters/1/stubs/0")).perform().unwrap();
+    assert_eq!(204, response.status());
+    let response = client.delete(&url("/imposters/1/stubs/0")).perform().unwrap();
+    assert_eq!(404, response.status());
+}
+
+#[test]
+fn it_can_post_and_disable_periodic_message() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [] }"#).unwrap());
+    let client = client(list.clone());
+    let doc = r#"{ "periodic": { "id": "0x401", "data": [ "0x01" ], "cycle": 100 } }"#;
+
+    let response = client.post(&url("/imposters/1/periodic"), doc, mime::APPLICATION_JSON).perform().unwrap();
+    assert_eq!(201, response.status());
+    assert_eq!("/imposters/1/periodic/0", response.headers().get("Location").unwrap());
+
+    let doc = r#"{ "id": "0x401", "data": [ "0x01" ], "cycle": 100, "enabled": false }"#;
+    let response = client.put(&url("/imposters/1/periodic/0"), doc, mime::APPLICATION_JSON).perform().unwrap();
+    assert_eq!(200, response.status());
+    assert_eq!(false, list.get_by_id(1).unwrap().periodic[0].is_enabled());
+}
+
+#[test]
+fn it_returns_400_with_path_for_invalid_periodic_message() {
+    let list = ImposterList::new();
+    list.upsert(Imposter::from_json(r#"{ "id": 1, "stubs": [] }"#).unwrap());
+    let client = client(list);
+    let doc = r#"{ "periodic": { "id": "0x401", "data": [], "cycle": 0 } }"#;
+
+    let response = client.post(&url("/imposters/1/periodic"), doc, mime::APPLICATION_JSON).perform().unwrap();
+
+    assert_eq!(400, response.status());
+    let body = as_json_obj(response);
+    assert_eq!("periodic.cycle", body["errors"][0]["path"]);
+
+    let doc = r#"{ "id": "0x401", "data": [], "cycle": 0 }"#;
+    let response = client.put(&url("/imposters/1/periodic/0"), doc, mime::APPLICATION_JSON).perform().unwrap();
+    assert_eq!(400, response.status());
+    assert_eq!("periodic.cycle", as_json_obj(response)["errors"][0]["path"]);
+}